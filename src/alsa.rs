@@ -0,0 +1,262 @@
+//! The ALSA backend, for systems without a sound server, which polls the status of every PCM substream in `/proc/asound`.
+//!
+//! procfs doesn't support inotify, so polling is the only option.
+//!
+//! Each running substream is reported as a stream owned by the process that opened it. Playback substreams are sink inputs and capture substreams are source outputs. Their indices are made of the card, device and substream numbers, so that card 1, device 0, substream 3 is stream 10003, and they keep them while other substreams come and go.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::backend::AudioSource;
+use crate::model::{Properties, SinkInput, SourceOutput, State};
+
+const ROOT: &str = "/proc/asound";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Alsa {
+	root: PathBuf,
+	running: Vec<Substream>,
+	state: State,
+}
+
+/// A substream in the `RUNNING` state.
+#[derive(Debug, PartialEq, Eq)]
+struct Substream {
+	/// Made of the card, device and substream numbers.
+	index: u32,
+	capture: bool,
+	/// The process that has the substream open, which the kernel only reports while it is open.
+	owner: Option<u32>,
+}
+
+impl Alsa {
+	pub fn open() -> io::Result<Self> {
+		let root = PathBuf::from(ROOT);
+		let running = running_substreams(&root)?;
+		Ok(Self {
+			root,
+			running,
+			state: State::default(),
+		})
+	}
+}
+
+impl AudioSource for Alsa {
+	fn wait(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			let interval = match deadline {
+				Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+					Some(remaining) if !remaining.is_zero() => remaining.min(POLL_INTERVAL),
+					_ => return Ok(false),
+				},
+				None => POLL_INTERVAL,
+			};
+			std::thread::sleep(interval);
+
+			let running = running_substreams(&self.root)?;
+			if running != self.running {
+				self.running = running;
+				return Ok(true);
+			}
+		}
+	}
+
+	fn state(&mut self) -> io::Result<&State> {
+		let mut state = State::default();
+		for substream in &self.running {
+			let index = substream.index;
+			let properties = substream.owner.map(process_properties).unwrap_or_default();
+			if substream.capture {
+				let stream = SourceOutput {
+					index,
+					client: None,
+					source: None,
+					properties,
+					corked: false,
+					mute: false,
+					volume: Vec::new(),
+					peak_detect: false,
+				};
+				state.source_outputs.insert(index, stream);
+			} else {
+				let stream = SinkInput {
+					index,
+					client: None,
+					sink: None,
+					properties,
+					corked: false,
+					mute: false,
+					volume: Vec::new(),
+					audible: None,
+				};
+				state.sink_inputs.insert(index, stream);
+			}
+		}
+		self.state = state;
+		Ok(&self.state)
+	}
+}
+
+/// Every substream of every PCM device of every card that is in the `RUNNING` state.
+///
+/// The layout is `card<N>/pcm<N><p|c>/sub<N>/status`. Substreams can disappear while we look, so errors below the root are ignored. They are sorted, so that the same substreams always compare equal.
+fn running_substreams(root: &Path) -> io::Result<Vec<Substream>> {
+	let cards = numbered_children(root, "card")?;
+	let substreams = cards
+		.flat_map(|(card, path)| {
+			let pcms = numbered_children(&path, "pcm").into_iter().flatten();
+			pcms.map(move |(device, path)| (card * 10_000 + device * 100, path))
+		})
+		.flat_map(|(base, pcm)| {
+			let capture = pcm.to_str().is_some_and(|pcm| pcm.ends_with('c'));
+			let substreams = numbered_children(&pcm, "sub").into_iter().flatten();
+			substreams.map(move |(number, substream)| (base + number, capture, substream))
+		});
+	let mut running: Vec<_> = substreams
+		.filter_map(|(index, capture, substream)| {
+			let status = fs::read_to_string(substream.join("status")).ok()?;
+			let mut lines = status.lines();
+			if lines.next() != Some("state: RUNNING") {
+				return None;
+			}
+			let owner = lines
+				.filter_map(|line| line.split_once(':'))
+				.find(|(key, _)| key.trim() == "owner_pid")
+				.and_then(|(_, pid)| pid.trim().parse().ok());
+			Some(Substream {
+				index,
+				capture,
+				owner,
+			})
+		})
+		.collect();
+	running.sort_by_key(|substream| (substream.capture, substream.index));
+	Ok(running)
+}
+
+/// The properties that a sound server would give a stream opened by the process `pid`.
+fn process_properties(pid: u32) -> Properties {
+	let mut properties = Properties::from([("application.process.id".into(), pid.to_string())]);
+	let proc = PathBuf::from(format!("/proc/{pid}"));
+	let binary = fs::read_link(proc.join("exe"))
+		.ok()
+		.and_then(|exe| Some(exe.file_name()?.to_str()?.to_owned()))
+		.or_else(|| {
+			Some(
+				fs::read_to_string(proc.join("comm"))
+					.ok()?
+					.trim_end()
+					.to_owned(),
+			)
+		});
+	if let Some(binary) = binary {
+		properties.insert("application.process.binary".into(), binary);
+	}
+	properties
+}
+
+/// The children of `dir` named `prefix` followed by a number, such as `card0` but not `cards`, with that number.
+fn numbered_children(
+	dir: &Path,
+	prefix: &'static str,
+) -> io::Result<impl Iterator<Item = (u32, PathBuf)>> {
+	Ok(fs::read_dir(dir)?.filter_map(move |entry| {
+		let entry = entry.ok()?;
+		let name = entry.file_name();
+		let suffix = name.to_str()?.strip_prefix(prefix)?;
+		let digits = suffix
+			.find(|ch: char| !ch.is_ascii_digit())
+			.unwrap_or(suffix.len());
+		let number = suffix[..digits].parse().ok()?;
+		Some((number, entry.path()))
+	}))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A directory laid out like `/proc/asound`, removed when dropped.
+	struct Fixture(PathBuf);
+
+	impl Fixture {
+		fn new(name: &str) -> Self {
+			let root = std::env::temp_dir().join(format!(
+				"{}-alsa-{name}-{}",
+				env!("CARGO_PKG_NAME"),
+				std::process::id()
+			));
+			_ = fs::remove_dir_all(&root);
+			fs::create_dir_all(&root).unwrap();
+			// Not a card, even though it starts like one.
+			fs::write(root.join("cards"), " 0 [PCH            ]: HDA-Intel\n").unwrap();
+			Self(root)
+		}
+
+		fn status(&self, substream: &str, status: &str) {
+			let dir = self.0.join(substream);
+			fs::create_dir_all(&dir).unwrap();
+			fs::write(dir.join("status"), status).unwrap();
+		}
+	}
+
+	impl Drop for Fixture {
+		fn drop(&mut self) {
+			_ = fs::remove_dir_all(&self.0);
+		}
+	}
+
+	const RUNNING: &str = "state: RUNNING\nowner_pid   : 4242\ntrigger_time: 1.5\n";
+
+	#[test]
+	fn running_substreams() {
+		let fixture = Fixture::new("substreams");
+		fixture.status("card0/pcm0p/sub0", RUNNING);
+		fixture.status("card0/pcm0p/sub1", "closed\n");
+		fixture.status("card0/pcm0c/sub0", "state: RUNNING\n");
+		fixture.status("card1/pcm3p/sub2", "state: PREPARED\nowner_pid   : 1\n");
+
+		assert_eq!(
+			super::running_substreams(&fixture.0).unwrap(),
+			[
+				Substream {
+					index: 0,
+					capture: false,
+					owner: Some(4242),
+				},
+				Substream {
+					index: 0,
+					capture: true,
+					owner: None,
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn stable_indices() {
+		let fixture = Fixture::new("indices");
+		fixture.status("card0/pcm0p/sub0", RUNNING);
+		fixture.status("card1/pcm3p/sub2", RUNNING);
+		let mut alsa = Alsa {
+			root: fixture.0.clone(),
+			running: super::running_substreams(&fixture.0).unwrap(),
+			state: State::default(),
+		};
+		let indices =
+			|alsa: &mut Alsa| -> Vec<u32> { alsa.state().unwrap().sink_inputs.keys().copied().collect() };
+		assert_eq!(indices(&mut alsa), [0, 10_302]);
+		assert_eq!(
+			alsa.state().unwrap().sink_inputs[&0].properties["application.process.id"],
+			"4242"
+		);
+
+		// The other substream keeps its index when the first one stops.
+		fixture.status("card0/pcm0p/sub0", "state: SETUP\n");
+		assert!(alsa.wait(Some(POLL_INTERVAL * 2)).unwrap());
+		assert_eq!(indices(&mut alsa), [10_302]);
+	}
+}
@@ -0,0 +1,215 @@
+//! The interface shared by all of the ways we have of finding out whether audio is playing, and the selection between them.
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::alsa::Alsa;
+use crate::mock::Mock;
+use crate::model::State;
+use crate::pactl::Pactl;
+use crate::policy::SilenceDetection;
+use crate::{pipewire, pulse};
+
+/// Sources are followed on a thread of their own, so they have to be [`Send`].
+pub trait AudioSource: Send {
+	/// Waits until the activity may have changed, or until `timeout` elapses.
+	///
+	/// Returns whether anything changed.
+	fn wait(&mut self, timeout: Option<Duration>) -> io::Result<bool>;
+
+	/// The streams and devices as they are now.
+	///
+	/// Whether they count as activity is up to the [`Policy`](crate::policy::Policy).
+	fn state(&mut self) -> io::Result<&State>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+	/// The Pulseaudio native protocol, also spoken by pipewire-pulse.
+	Pulse,
+	/// The Pipewire native protocol.
+	Pipewire,
+	/// `pactl subscribe` and `pactl list`.
+	Pactl,
+	/// The state of ALSA PCM substreams, for systems without a sound server.
+	Alsa,
+	/// Activity read from standard input.
+	Mock,
+}
+
+impl Backend {
+	pub const ALL: [Self; 5] = [
+		Self::Pulse,
+		Self::Pipewire,
+		Self::Pactl,
+		Self::Alsa,
+		Self::Mock,
+	];
+
+	/// The backends to try, in order, if the preferred one is unavailable. The mock backend is never chosen automatically.
+	const FALLBACKS: [Self; 4] = [Self::Pulse, Self::Pipewire, Self::Pactl, Self::Alsa];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Pulse => "pulse",
+			Self::Pipewire => "pipewire",
+			Self::Pactl => "pactl",
+			Self::Alsa => "alsa",
+			Self::Mock => "mock",
+		}
+	}
+
+	/// Whether the backend measures the level of playback streams, which is needed for silence detection.
+	pub fn detects_silence(self) -> bool {
+		self == Self::Pulse
+	}
+
+	pub fn connect(
+		self,
+		silence_detection: Option<SilenceDetection>,
+	) -> io::Result<Box<dyn AudioSource>> {
+		Ok(match self {
+			Self::Pulse => Box::new(pulse::Client::connect(silence_detection)?),
+			Self::Pipewire => Box::new(pipewire::Client::connect()?),
+			Self::Pactl => Box::new(Pactl::spawn()?),
+			Self::Alsa => Box::new(Alsa::open()?),
+			Self::Mock => Box::new(Mock::new()),
+		})
+	}
+}
+
+impl Display for Backend {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter.write_str(self.name())
+	}
+}
+
+#[derive(Debug)]
+pub struct UnknownBackend(String);
+
+impl Display for UnknownBackend {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		write!(formatter, "unknown backend {:?}, expected one of", self.0)?;
+		for backend in Backend::ALL {
+			write!(formatter, " {backend}")?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for UnknownBackend {}
+
+impl FromStr for Backend {
+	type Err = UnknownBackend;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|backend| backend.name() == name)
+			.ok_or_else(|| UnknownBackend(name.to_owned()))
+	}
+}
+
+/// Connects to the preferred backend, falling back to the others in order if it is unavailable.
+///
+/// Returns `None` if no backend is available. Failures are reported on standard error as they happen.
+pub fn connect(
+	preferred: Option<Backend>,
+	silence_detection: Option<SilenceDetection>,
+) -> Option<(Backend, Box<dyn AudioSource>)> {
+	first_available(preferred, |backend| {
+		let source = backend.connect(silence_detection)?;
+		if silence_detection.is_some() && !backend.detects_silence() {
+			eprintln!("the {backend} backend cannot detect silence, so all playing streams count");
+		}
+		Ok(source)
+	})
+}
+
+/// Tries `connect` with the preferred backend and then with the fallbacks, returning the first backend that it succeeds with.
+fn first_available<T>(
+	preferred: Option<Backend>,
+	mut connect: impl FnMut(Backend) -> io::Result<T>,
+) -> Option<(Backend, T)> {
+	let fallbacks = Backend::FALLBACKS
+		.into_iter()
+		.filter(|&backend| Some(backend) != preferred);
+	preferred
+		.into_iter()
+		.chain(fallbacks)
+		.find_map(|backend| match connect(backend) {
+			Ok(connected) => Some((backend, connected)),
+			Err(error) => {
+				eprintln!("{backend} backend is unavailable: {error}");
+				None
+			}
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Tries to connect, with only the backends in `available` succeeding.
+	///
+	/// Returns the backend connected to, and every backend tried.
+	fn try_connect(
+		preferred: Option<Backend>,
+		available: &[Backend],
+	) -> (Option<Backend>, Vec<Backend>) {
+		let mut tried = Vec::new();
+		let connected = first_available(preferred, |backend| {
+			tried.push(backend);
+			if available.contains(&backend) {
+				Ok(())
+			} else {
+				Err(io::Error::new(io::ErrorKind::NotFound, "not running"))
+			}
+		});
+		(connected.map(|(backend, ())| backend), tried)
+	}
+
+	#[test]
+	fn preferred_first() {
+		let (connected, tried) = try_connect(Some(Backend::Alsa), &Backend::ALL);
+		assert_eq!(connected, Some(Backend::Alsa));
+		assert_eq!(tried, [Backend::Alsa]);
+
+		// Only when asked for.
+		let (connected, _) = try_connect(Some(Backend::Mock), &[Backend::Mock, Backend::Pactl]);
+		assert_eq!(connected, Some(Backend::Mock));
+	}
+
+	#[test]
+	fn fallback_in_order() {
+		let (connected, tried) = try_connect(None, &[Backend::Pactl, Backend::Alsa]);
+		assert_eq!(connected, Some(Backend::Pactl));
+		assert_eq!(tried, [Backend::Pulse, Backend::Pipewire, Backend::Pactl]);
+
+		// The preferred backend isn't tried again among the fallbacks.
+		let (connected, tried) = try_connect(Some(Backend::Pipewire), &[Backend::Alsa]);
+		assert_eq!(connected, Some(Backend::Alsa));
+		assert_eq!(
+			tried,
+			[
+				Backend::Pipewire,
+				Backend::Pulse,
+				Backend::Pactl,
+				Backend::Alsa
+			]
+		);
+	}
+
+	#[test]
+	fn none_available() {
+		let (connected, tried) = try_connect(None, &[Backend::Mock]);
+		assert_eq!(connected, None);
+		assert_eq!(tried, Backend::FALLBACKS);
+
+		let (connected, tried) = try_connect(Some(Backend::Mock), &[]);
+		assert_eq!(connected, None);
+		assert_eq!(tried.len(), Backend::ALL.len());
+	}
+}
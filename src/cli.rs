@@ -0,0 +1,292 @@
+//! The command-line interface.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::backend::Backend;
+use crate::config::Settings;
+use crate::policy::{Direction, Matcher, SilenceDetection};
+use crate::timing::parse_duration;
+
+/// Inhibits idle while audio is playing or recording.
+///
+/// Without a command, runs the daemon as `run` does.
+#[derive(Debug, Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+	/// The configuration file to use instead of `$XDG_CONFIG_HOME/pulse-inhibit/config.toml`.
+	#[arg(long, global = true, value_name = "PATH")]
+	pub config: Option<PathBuf>,
+
+	/// The control socket to use instead of `$XDG_RUNTIME_DIR/pulse-inhibit.sock`.
+	#[arg(long, global = true, value_name = "PATH")]
+	pub socket: Option<PathBuf>,
+
+	#[command(subcommand)]
+	pub command: Option<Command>,
+
+	/// The arguments to `run`, when no command is given.
+	#[command(flatten)]
+	pub run: SettingsArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Inhibits idle while audio counts as activity, until killed.
+	Run(SettingsArgs),
+	/// Prints whether idle is inhibited, asking the running instance if there is one.
+	///
+	/// Otherwise, prints whether audio currently counts as activity.
+	Status(SettingsArgs),
+	/// Checks the configuration file and exits with an error if it is invalid.
+	CheckConfig,
+	/// Prints every stream the backend sees and whether it counts as activity.
+	ListStreams(SettingsArgs),
+	/// Prints the streams that count as activity, and why, asking the running instance if there is one.
+	Why(SettingsArgs),
+	/// Makes the running instance inhibit idle whatever the audio is doing, until `uninhibit`.
+	Inhibit {
+		/// Only inhibit for this long, such as `90`, `45m` or `1h30m`.
+		#[arg(long = "for", value_name = "DURATION", value_parser = parse_duration)]
+		duration: Option<Duration>,
+	},
+	/// Withdraws `inhibit`, so that only audio inhibits idle.
+	Uninhibit,
+	/// Sends a request to the running instance, printing the replies as JSON.
+	///
+	/// The requests are `status`, `pause`, `resume`, `inhibit [DURATION]`, `uninhibit`, `reload` and `subscribe`.
+	Control {
+		#[arg(required = true, value_name = "REQUEST")]
+		request: Vec<String>,
+	},
+}
+
+/// Arguments that override the configuration file.
+#[derive(Debug, Args)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct SettingsArgs {
+	/// The backend to try first. The others are still tried if it is unavailable.
+	#[arg(long, value_name = "NAME")]
+	pub backend: Option<Backend>,
+
+	/// Only count streams matching one of these rules.
+	#[arg(long, value_name = "KEY=PATTERN")]
+	pub allow: Vec<Matcher>,
+	/// Never count streams matching one of these rules.
+	#[arg(long, value_name = "KEY=PATTERN")]
+	pub deny: Vec<Matcher>,
+	/// Like `--allow`, for playback streams only.
+	#[arg(long, value_name = "KEY=PATTERN")]
+	pub allow_playback: Vec<Matcher>,
+	/// Like `--deny`, for playback streams only.
+	#[arg(long, value_name = "KEY=PATTERN")]
+	pub deny_playback: Vec<Matcher>,
+	/// Like `--allow`, for capture streams only.
+	#[arg(long, value_name = "KEY=PATTERN")]
+	pub allow_capture: Vec<Matcher>,
+	/// Like `--deny`, for capture streams only.
+	#[arg(long, value_name = "KEY=PATTERN")]
+	pub deny_capture: Vec<Matcher>,
+
+	/// Only count playback streams playing to a sink matching one of these rules.
+	#[arg(long, value_name = "KEY=PATTERN", value_parser = Matcher::parse_device)]
+	pub allow_sink: Vec<Matcher>,
+	/// Never count playback streams playing to a sink matching one of these rules.
+	#[arg(long, value_name = "KEY=PATTERN", value_parser = Matcher::parse_device)]
+	pub deny_sink: Vec<Matcher>,
+	/// Only count capture streams recording from a source matching one of these rules.
+	#[arg(long, value_name = "KEY=PATTERN", value_parser = Matcher::parse_device)]
+	pub allow_source: Vec<Matcher>,
+	/// Never count capture streams recording from a source matching one of these rules.
+	#[arg(long, value_name = "KEY=PATTERN", value_parser = Matcher::parse_device)]
+	pub deny_source: Vec<Matcher>,
+
+	/// Don't count playback streams at all.
+	#[arg(long)]
+	pub no_playback: bool,
+	/// Don't count capture streams at all.
+	#[arg(long)]
+	pub no_capture: bool,
+	/// Count capture streams that record from monitor sources or only measure levels.
+	#[arg(long)]
+	pub count_monitors: bool,
+	/// Only count streams with these `media.role`s, instead of the default ones. Streams without a role always count.
+	#[arg(long = "role", value_name = "ROLE")]
+	pub roles: Vec<String>,
+	/// Don't count streams that are muted or at zero volume.
+	#[arg(long)]
+	pub ignore_muted_streams: bool,
+	/// Don't count streams whose sink or source is muted or at zero volume.
+	#[arg(long)]
+	pub ignore_muted_devices: bool,
+
+	/// Don't count playback streams that are playing silence.
+	#[arg(long)]
+	pub detect_silence: bool,
+	/// The level in dBFS below which a stream is silent. Implies `--detect-silence`.
+	#[arg(
+		long,
+		value_name = "DB",
+		allow_negative_numbers = true,
+		value_parser = threshold
+	)]
+	pub silence_threshold: Option<f32>,
+	/// How long a stream's level has to stay above or below the threshold to change whether it counts. Implies `--detect-silence`.
+	#[arg(long, value_name = "SECONDS", value_parser = seconds)]
+	pub silence_duration: Option<Duration>,
+
+	/// How long to wait for bursts of changes to settle.
+	#[arg(long, value_name = "SECONDS", value_parser = seconds)]
+	pub debounce: Option<Duration>,
+	/// How long audio has to be active before idle is inhibited.
+	#[arg(long, value_name = "SECONDS", value_parser = seconds)]
+	pub activation_delay: Option<Duration>,
+	/// How long idle stays inhibited after audio stops.
+	#[arg(long, value_name = "SECONDS", value_parser = seconds)]
+	pub linger: Option<Duration>,
+}
+
+impl SettingsArgs {
+	/// Applies the arguments on top of the configuration file.
+	pub fn apply(&self, settings: &mut Settings) {
+		let Settings {
+			backend,
+			policy,
+			timings,
+			targets: _,
+			logind: _,
+			portal: _,
+		} = settings;
+
+		if self.backend.is_some() {
+			*backend = self.backend;
+		}
+
+		let add = |direction: &mut Direction, allow: &[Matcher], deny: &[Matcher]| {
+			direction.rules.allow.extend(allow.iter().cloned());
+			direction.rules.deny.extend(deny.iter().cloned());
+		};
+		add(&mut policy.playback, &self.allow, &self.deny);
+		add(
+			&mut policy.playback,
+			&self.allow_playback,
+			&self.deny_playback,
+		);
+		add(&mut policy.capture, &self.allow, &self.deny);
+		add(&mut policy.capture, &self.allow_capture, &self.deny_capture);
+
+		let add_devices = |direction: &mut Direction, allow: &[Matcher], deny: &[Matcher]| {
+			direction.devices.allow.extend(allow.iter().cloned());
+			direction.devices.deny.extend(deny.iter().cloned());
+		};
+		add_devices(&mut policy.playback, &self.allow_sink, &self.deny_sink);
+		add_devices(&mut policy.capture, &self.allow_source, &self.deny_source);
+
+		if self.no_playback {
+			policy.playback.enabled = false;
+		}
+		if self.no_capture {
+			policy.capture.enabled = false;
+		}
+		if !self.roles.is_empty() {
+			policy.roles = self.roles.iter().cloned().collect();
+		}
+		policy.count_monitors |= self.count_monitors;
+		policy.ignore_muted_streams |= self.ignore_muted_streams;
+		policy.ignore_muted_devices |= self.ignore_muted_devices;
+
+		if self.detect_silence || self.silence_threshold.is_some() || self.silence_duration.is_some() {
+			let silence_detection = policy.silence_detection.get_or_insert_default();
+			if let Some(threshold) = self.silence_threshold {
+				silence_detection.threshold = threshold;
+			}
+			if let Some(duration) = self.silence_duration {
+				silence_detection.duration = duration;
+			}
+		}
+
+		if let Some(debounce) = self.debounce {
+			timings.debounce = debounce;
+		}
+		if let Some(activation_delay) = self.activation_delay {
+			timings.activation_delay = activation_delay;
+		}
+		if let Some(linger) = self.linger {
+			timings.linger = linger;
+		}
+	}
+}
+
+fn seconds(value: &str) -> Result<Duration, String> {
+	value
+		.parse()
+		.ok()
+		.and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+		.ok_or_else(|| format!("{value:?} is not a valid number of seconds"))
+}
+
+fn threshold(value: &str) -> Result<f32, String> {
+	let threshold = value
+		.parse()
+		.map_err(|_| format!("{value:?} is not a number of dBFS"))?;
+	SilenceDetection::check_threshold(threshold)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn definition() {
+		use clap::CommandFactory;
+		Cli::command().debug_assert();
+	}
+
+	#[test]
+	fn overrides() {
+		let cli = Cli::try_parse_from([
+			"pulse-inhibit",
+			"run",
+			"--deny",
+			"application.name=dunst",
+			"--no-capture",
+			"--debounce",
+			"0.5",
+			"--silence-threshold",
+			"-60",
+		])
+		.unwrap();
+		let Some(Command::Run(args)) = cli.command else {
+			panic!("expected `run`");
+		};
+
+		let mut settings = Settings::default();
+		settings.policy.roles.clear();
+		args.apply(&mut settings);
+		assert_eq!(settings.policy.playback.rules.deny.len(), 1);
+		assert_eq!(settings.policy.capture.rules.deny.len(), 1);
+		assert!(!settings.policy.capture.enabled);
+		assert!(settings.policy.roles.is_empty());
+		assert_eq!(settings.timings.debounce, Duration::from_millis(500));
+		let silence_detection = settings.policy.silence_detection.unwrap();
+		assert!((silence_detection.threshold - -60.0).abs() < f32::EPSILON);
+	}
+
+	#[test]
+	fn default_command() {
+		let cli = Cli::try_parse_from(["pulse-inhibit", "--linger", "30"]).unwrap();
+		assert!(cli.command.is_none());
+		assert_eq!(cli.run.linger, Some(Duration::from_secs(30)));
+
+		let error = Cli::try_parse_from(["pulse-inhibit", "--allow", "client.id=1"]).unwrap_err();
+		assert!(error.to_string().contains("cannot match on \"client.id\""));
+
+		for threshold in ["6", "NaN", "inf", "-inf"] {
+			let argument = format!("--silence-threshold={threshold}");
+			let error = Cli::try_parse_from(["pulse-inhibit", &argument]).unwrap_err();
+			assert!(error.to_string().contains("not a valid threshold"));
+		}
+	}
+}
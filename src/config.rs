@@ -0,0 +1,534 @@
+//! The configuration file, `$XDG_CONFIG_HOME/pulse-inhibit/config.toml`.
+//!
+//! Everything in it is optional. Command-line arguments are applied on top of it.
+
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::AsFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use rustix::fs::inotify::{self, CreateFlags, WatchFlags};
+use rustix::io::Errno;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+use crate::backend::Backend;
+use crate::logind::{self, Lock};
+use crate::policy::{Direction, Matcher, Policy, SilenceDetection};
+use crate::portal::Flags;
+use crate::timing::Timings;
+
+/// Where idle can be inhibited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Target {
+	/// The compositor's idle-inhibit-unstable-v1 protocol.
+	Wayland,
+	/// `org.freedesktop.ScreenSaver` on the session bus.
+	#[serde(rename = "screensaver")]
+	ScreenSaver,
+	/// A systemd-logind inhibitor lock on the system bus, as set in [`Settings::logind`].
+	Logind,
+	/// The `org.freedesktop.portal.Inhibit` desktop portal, as set in [`Settings::portal`].
+	Portal,
+}
+
+/// Everything that can be configured, from the file and then the command line.
+#[derive(Debug)]
+pub struct Settings {
+	pub backend: Option<Backend>,
+	pub targets: Vec<Target>,
+	/// The lock to take if [`Target::Logind`] is one of the targets.
+	pub logind: Lock,
+	/// What to inhibit besides idle if [`Target::Portal`] is one of the targets.
+	pub portal: Flags,
+	pub policy: Policy,
+	pub timings: Timings,
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			backend: None,
+			targets: vec![Target::Wayland],
+			logind: Lock::default(),
+			portal: Flags::default(),
+			policy: Policy::default(),
+			timings: Timings::default(),
+		}
+	}
+}
+
+impl Settings {
+	/// Reads the configuration file. A missing file is the same as an empty one.
+	pub fn load(path: &Path) -> Result<Self, Error> {
+		let text = match std::fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+			Err(error) => return Err(Error::Read(error)),
+		};
+		let config: Config = toml::from_str(&text).map_err(Error::Parse)?;
+		config.try_into().map_err(Error::Invalid)
+	}
+}
+
+#[derive(Debug)]
+pub enum Error {
+	Read(io::Error),
+	Parse(toml::de::Error),
+	/// The file parses, but asks for something that can't be done.
+	Invalid(String),
+}
+
+impl Display for Error {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Read(error) => write!(formatter, "cannot read the configuration: {error}"),
+			Self::Parse(error) => write!(formatter, "invalid configuration: {error}"),
+			Self::Invalid(message) => write!(formatter, "invalid configuration: {message}"),
+		}
+	}
+}
+
+/// The configuration file's location: `$XDG_CONFIG_HOME/pulse-inhibit/config.toml`, where `$XDG_CONFIG_HOME` defaults to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+	let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+		.map(PathBuf::from)
+		.or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")))?;
+	Some(config_dir.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+}
+
+/// Calls `changed` from a new thread whenever the file at `path` may have changed, until it returns `false`.
+///
+/// Editors often replace files rather than writing to them, so we watch the directory for anything happening to that name.
+/// If the directory doesn't exist yet, we watch its nearest existing ancestor until it's created.
+pub fn watch(path: &Path, mut changed: impl FnMut() -> bool + Send + 'static) -> io::Result<()> {
+	let directory = path
+		.parent()
+		.ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"configuration path has no parent",
+			)
+		})?
+		.to_owned();
+	let name: OsString = path
+		.file_name()
+		.ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"configuration path has no file name",
+			)
+		})?
+		.to_owned();
+	let path = path.to_owned();
+
+	let inotify = inotify::init(CreateFlags::CLOEXEC)?;
+	let mut watching = Watch::add(&inotify, &directory)?;
+
+	std::thread::spawn(move || {
+		let mut buffer = [MaybeUninit::uninit(); 4096];
+		let mut events = inotify::Reader::new(&inotify, &mut buffer);
+		while let Ok(event) = events.next() {
+			let Some(file_name) = event.file_name() else {
+				continue;
+			};
+			let file_name = OsStr::from_bytes(file_name.to_bytes());
+			if watching.directory == directory {
+				if file_name == name && !changed() {
+					break;
+				}
+			} else if directory.starts_with(watching.directory.join(file_name)) {
+				// The next directory down was created, so watch as far down as now exists.
+				_ = inotify::remove_watch(&inotify, watching.descriptor);
+				match Watch::add(&inotify, &directory) {
+					Ok(watch) => watching = watch,
+					Err(_) => break,
+				}
+				if watching.directory == directory && path.exists() && !changed() {
+					break;
+				}
+			}
+		}
+	});
+
+	Ok(())
+}
+
+/// The directory being watched for the configuration file, or for the next directory on the way to it.
+struct Watch {
+	directory: PathBuf,
+	descriptor: i32,
+}
+
+impl Watch {
+	/// Watches `directory`, or its nearest existing ancestor.
+	fn add(inotify: impl AsFd, directory: &Path) -> io::Result<Self> {
+		let mut ancestor = directory;
+		loop {
+			let flags = if ancestor == directory {
+				WatchFlags::CLOSE_WRITE | WatchFlags::MOVED_TO | WatchFlags::DELETE
+			} else {
+				WatchFlags::CREATE | WatchFlags::MOVED_TO
+			};
+			match inotify::add_watch(&inotify, ancestor, flags) {
+				Ok(descriptor) => {
+					return Ok(Self {
+						directory: ancestor.to_owned(),
+						descriptor,
+					})
+				}
+				Err(Errno::NOENT) => {
+					ancestor = ancestor.parent().ok_or_else(|| {
+						io::Error::new(
+							io::ErrorKind::NotFound,
+							"no ancestor of the configuration directory exists",
+						)
+					})?;
+				}
+				Err(error) => return Err(error.into()),
+			}
+		}
+	}
+}
+
+/// The file as written. Every field is optional, falling back to the defaults in [`Settings`].
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+	backend: Option<Parsed<Backend>>,
+	targets: Option<Vec<Target>>,
+	logind: LogindConfig,
+	portal: PortalConfig,
+	/// Rules for both directions.
+	allow: Vec<Parsed<Matcher>>,
+	deny: Vec<Parsed<Matcher>>,
+	roles: Option<BTreeSet<String>>,
+	ignore_muted_streams: bool,
+	ignore_muted_devices: bool,
+	count_monitors: bool,
+	silence_detection: Option<SilenceDetectionConfig>,
+	playback: DirectionConfig,
+	capture: DirectionConfig,
+	timings: TimingsConfig,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct LogindConfig {
+	what: Option<BTreeSet<logind::What>>,
+	mode: Option<logind::Mode>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct PortalConfig {
+	suspend: bool,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct DirectionConfig {
+	enabled: Option<bool>,
+	allow: Vec<Parsed<Matcher>>,
+	deny: Vec<Parsed<Matcher>>,
+	allow_devices: Vec<DeviceMatcher>,
+	deny_devices: Vec<DeviceMatcher>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct SilenceDetectionConfig {
+	threshold: Option<Threshold>,
+	duration: Option<Seconds>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct TimingsConfig {
+	debounce: Option<Seconds>,
+	activation_delay: Option<Seconds>,
+	linger: Option<Seconds>,
+}
+
+impl TryFrom<Config> for Settings {
+	type Error = String;
+
+	fn try_from(config: Config) -> Result<Self, Self::Error> {
+		let defaults = Self::default();
+
+		let mut policy = defaults.policy;
+		for (direction, overrides) in [
+			(&mut policy.playback, config.playback),
+			(&mut policy.capture, config.capture),
+		] {
+			apply_direction(direction, overrides, &config.allow, &config.deny);
+		}
+		if let Some(roles) = config.roles {
+			policy.roles = roles;
+		}
+		policy.ignore_muted_streams = config.ignore_muted_streams;
+		policy.ignore_muted_devices = config.ignore_muted_devices;
+		policy.count_monitors = config.count_monitors;
+		policy.silence_detection = config.silence_detection.map(|overrides| {
+			let defaults = SilenceDetection::default();
+			SilenceDetection {
+				threshold: overrides
+					.threshold
+					.map_or(defaults.threshold, |Threshold(threshold)| threshold),
+				duration: overrides
+					.duration
+					.map_or(defaults.duration, |Seconds(duration)| duration),
+			}
+		});
+
+		let timings = Timings {
+			debounce: config
+				.timings
+				.debounce
+				.map_or(defaults.timings.debounce, |Seconds(duration)| duration),
+			activation_delay: config
+				.timings
+				.activation_delay
+				.map_or(defaults.timings.activation_delay, |Seconds(duration)| {
+					duration
+				}),
+			linger: config
+				.timings
+				.linger
+				.map_or(defaults.timings.linger, |Seconds(duration)| duration),
+		};
+
+		let logind = Lock {
+			what: config.logind.what.unwrap_or(defaults.logind.what),
+			mode: config.logind.mode.unwrap_or(defaults.logind.mode),
+		};
+		logind.check()?;
+
+		Ok(Self {
+			backend: config.backend.map(|Parsed(backend)| backend),
+			targets: config.targets.unwrap_or(defaults.targets),
+			logind,
+			portal: Flags {
+				suspend: config.portal.suspend,
+			},
+			policy,
+			timings,
+		})
+	}
+}
+
+fn matchers(list: &[Parsed<Matcher>]) -> impl Iterator<Item = Matcher> + '_ {
+	list.iter().map(|Parsed(matcher)| matcher.clone())
+}
+
+fn apply_direction(
+	direction: &mut Direction,
+	config: DirectionConfig,
+	allow: &[Parsed<Matcher>],
+	deny: &[Parsed<Matcher>],
+) {
+	let devices = |list: Vec<DeviceMatcher>| list.into_iter().map(|DeviceMatcher(matcher)| matcher);
+
+	if let Some(enabled) = config.enabled {
+		direction.enabled = enabled;
+	}
+	direction
+		.rules
+		.allow
+		.extend(matchers(allow).chain(matchers(&config.allow)));
+	direction
+		.rules
+		.deny
+		.extend(matchers(deny).chain(matchers(&config.deny)));
+	direction
+		.devices
+		.allow
+		.extend(devices(config.allow_devices));
+	direction.devices.deny.extend(devices(config.deny_devices));
+}
+
+/// A value written as a string and parsed with its [`FromStr`] implementation.
+struct Parsed<T>(T);
+
+impl<'de, T> Deserialize<'de> for Parsed<T>
+where
+	T: FromStr,
+	T::Err: Display,
+{
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let raw = String::deserialize(deserializer)?;
+		raw.parse().map(Self).map_err(de::Error::custom)
+	}
+}
+
+/// A [`Matcher`] for a device rule.
+struct DeviceMatcher(Matcher);
+
+impl<'de> Deserialize<'de> for DeviceMatcher {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let raw = String::deserialize(deserializer)?;
+		Matcher::parse_device(&raw)
+			.map(Self)
+			.map_err(de::Error::custom)
+	}
+}
+
+/// A duration written as a number of seconds.
+struct Seconds(Duration);
+
+impl<'de> Deserialize<'de> for Seconds {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let seconds = f64::deserialize(deserializer)?;
+		Duration::try_from_secs_f64(seconds)
+			.map(Self)
+			.map_err(|_| de::Error::custom(format!("{seconds} is not a valid number of seconds")))
+	}
+}
+
+/// A [`SilenceDetection::threshold`], in dBFS.
+struct Threshold(f32);
+
+impl<'de> Deserialize<'de> for Threshold {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let threshold = f32::deserialize(deserializer)?;
+		SilenceDetection::check_threshold(threshold)
+			.map(Self)
+			.map_err(de::Error::custom)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn empty() {
+		let settings: Settings = toml::from_str::<Config>("").unwrap().try_into().unwrap();
+		assert_eq!(settings.targets, [Target::Wayland]);
+		assert_eq!(settings.timings.debounce, Timings::default().debounce);
+	}
+
+	#[test]
+	fn full() {
+		let config = r#"
+			backend = "pactl"
+			targets = ["wayland", "screensaver", "logind", "portal"]
+			deny = ["application.name=dunst"]
+			roles = ["music", "video"]
+			ignore-muted-streams = true
+			count-monitors = true
+
+			[logind]
+			what = ["idle"]
+
+			[portal]
+			suspend = true
+
+			[silence-detection]
+			threshold = -60
+
+			[playback]
+			allow = ["application.process.binary=mpv"]
+			allow-devices = ["name=*hdmi*"]
+
+			[capture]
+			enabled = false
+
+			[timings]
+			linger = 30
+			activation-delay = 2.5
+		"#;
+		let settings: Settings = toml::from_str::<Config>(config)
+			.unwrap()
+			.try_into()
+			.unwrap();
+		assert_eq!(settings.backend, Some(Backend::Pactl));
+		assert_eq!(
+			settings.targets,
+			[
+				Target::Wayland,
+				Target::ScreenSaver,
+				Target::Logind,
+				Target::Portal
+			]
+		);
+		assert_eq!(settings.logind.what, [logind::What::Idle].into());
+		assert_eq!(settings.logind.mode, logind::Mode::Block);
+		assert!(settings.portal.suspend);
+		let policy = &settings.policy;
+		assert_eq!(policy.playback.rules.allow.len(), 1);
+		assert_eq!(policy.playback.rules.deny.len(), 1);
+		assert_eq!(policy.capture.rules.deny.len(), 1);
+		assert_eq!(policy.playback.devices.allow.len(), 1);
+		assert!(!policy.capture.enabled);
+		assert_eq!(policy.roles.len(), 2);
+		assert!(policy.ignore_muted_streams);
+		assert!(policy.count_monitors);
+		let silence_detection = policy.silence_detection.unwrap();
+		assert!((silence_detection.threshold - -60.0).abs() < f32::EPSILON);
+		assert_eq!(
+			silence_detection.duration,
+			SilenceDetection::default().duration
+		);
+		assert_eq!(settings.timings.linger, Duration::from_secs(30));
+		assert_eq!(
+			settings.timings.activation_delay,
+			Duration::from_millis(2500)
+		);
+	}
+
+	#[test]
+	fn watch_missing_directory() {
+		let root = std::env::temp_dir().join(format!(
+			"{}-config-{}",
+			env!("CARGO_PKG_NAME"),
+			std::process::id()
+		));
+		_ = std::fs::remove_dir_all(&root);
+		std::fs::create_dir(&root).unwrap();
+		let directory = root.join("config").join(env!("CARGO_PKG_NAME"));
+		let path = directory.join("config.toml");
+
+		let (sender, receiver) = std::sync::mpsc::channel();
+		watch(&path, move || sender.send(()).is_ok()).unwrap();
+		std::fs::create_dir_all(&directory).unwrap();
+		std::fs::write(&path, "").unwrap();
+		let changed = receiver.recv_timeout(Duration::from_secs(5));
+		_ = std::fs::remove_dir_all(&root);
+		assert!(changed.is_ok());
+	}
+
+	#[test]
+	fn errors() {
+		let error = |config| toml::from_str::<Config>(config).err().unwrap().to_string();
+		assert!(error("backend = \"jack\"").contains("unknown backend \"jack\""));
+		assert!(error("allow = [\"client.id=1\"]").contains("cannot match on \"client.id\""));
+		assert!(error("[playback]\nallow-devices = [\"media.name=x\"]")
+			.contains("cannot match on \"media.name\""));
+		assert!(error("[timings]\nlinger = -1").contains("not a valid number of seconds"));
+		assert!(error("[silence-detection]\nthreshold = 6").contains("not a valid threshold"));
+		assert!(error("[silence-detection]\nthreshold = nan").contains("not a valid threshold"));
+		assert!(error("[silence-detection]\nthreshold = -inf").contains("not a valid threshold"));
+		assert!(error("lingre = 1").contains("unknown field `lingre`"));
+
+		let invalid =
+			|config| Settings::try_from(toml::from_str::<Config>(config).unwrap()).unwrap_err();
+		assert!(invalid("[logind]\nwhat = []").contains("nothing"));
+		assert!(invalid("[logind]\nmode = \"delay\"").contains("not idle"));
+		assert!(
+			invalid("[logind]\nwhat = [\"sleep\", \"handle-lid-switch\"]\nmode = \"delay\"")
+				.contains("not handle-lid-switch")
+		);
+		assert!(Settings::try_from(
+			toml::from_str::<Config>("[logind]\nwhat = [\"sleep\"]\nmode = \"delay\"").unwrap()
+		)
+		.is_ok());
+	}
+}
@@ -0,0 +1,266 @@
+//! The control socket, `$XDG_RUNTIME_DIR/pulse-inhibit.sock`, through which scripts and bars can talk to a running instance.
+//!
+//! Each request is a line of text, such as `status` or `inhibit 45m`, and each reply is a line of JSON with a `type` of `status`, `ok` or `error`. The requests are:
+//!
+//! - `status`: whether idle is inhibited, and by which streams.
+//! - `pause`: stop audio from inhibiting idle.
+//! - `resume`: let audio inhibit idle again.
+//! - `inhibit [duration]`: inhibit idle whatever the audio is doing, for that long (such as `45m`) or until `uninhibit`. Also accepted as `force-inhibit`.
+//! - `uninhibit`: withdraw `inhibit`.
+//! - `reload`: reload the configuration file.
+//! - `subscribe`: reply with the status now and whenever it changes, until the connection is closed.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::explain::Contribution;
+use crate::inhibit::Reason;
+use crate::timing::parse_duration;
+
+/// How long to wait for a client to read a reply before giving up on it.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The socket's location, if `$XDG_RUNTIME_DIR` is set.
+pub fn default_path() -> Option<PathBuf> {
+	let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+	Some(PathBuf::from(runtime_dir).join(concat!(env!("CARGO_PKG_NAME"), ".sock")))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+	Status,
+	Pause,
+	Resume,
+	/// Inhibit for this long, or until withdrawn.
+	Inhibit(Option<Duration>),
+	Uninhibit,
+	Reload,
+	Subscribe,
+}
+
+impl FromStr for Request {
+	type Err = String;
+
+	fn from_str(line: &str) -> Result<Self, Self::Err> {
+		let mut words = line.split_whitespace();
+		let request = match words.next().unwrap_or_default() {
+			"status" => Self::Status,
+			"pause" => Self::Pause,
+			"resume" => Self::Resume,
+			"inhibit" | "force-inhibit" => Self::Inhibit(words.next().map(parse_duration).transpose()?),
+			"uninhibit" => Self::Uninhibit,
+			"reload" => Self::Reload,
+			"subscribe" => Self::Subscribe,
+			other => return Err(format!("unknown request {other:?}")),
+		};
+		match words.next() {
+			Some(extra) => Err(format!("unexpected {extra:?}")),
+			None => Ok(request),
+		}
+	}
+}
+
+impl Display for Request {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Status => formatter.write_str("status"),
+			Self::Pause => formatter.write_str("pause"),
+			Self::Resume => formatter.write_str("resume"),
+			Self::Inhibit(None) => formatter.write_str("inhibit"),
+			Self::Inhibit(Some(duration)) => write!(formatter, "inhibit {}", duration.as_secs_f64()),
+			Self::Uninhibit => formatter.write_str("uninhibit"),
+			Self::Reload => formatter.write_str("reload"),
+			Self::Subscribe => formatter.write_str("subscribe"),
+		}
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Reply {
+	Status(Status),
+	Ok,
+	Error { message: String },
+}
+
+/// What a running instance is doing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Status {
+	pub inhibited: bool,
+	/// Why idle is inhibited.
+	pub reasons: Vec<Reason>,
+	/// Whether audio counts as activity, even if paused.
+	pub active: bool,
+	/// Whether audio is kept from inhibiting idle.
+	pub paused: bool,
+	/// Whether idle is inhibited manually.
+	pub manual: bool,
+	/// How much longer the manual inhibit lasts, if it doesn't last until withdrawn.
+	#[serde(with = "optional_seconds")]
+	pub manual_for: Option<Duration>,
+	/// The streams that count as activity.
+	pub streams: Vec<Contribution>,
+}
+
+/// Listens on `path`, calling `handle` from other threads with each request and the connection to reply on, until it returns `false`.
+///
+/// Refuses to take over the socket of another running instance; one left behind by an instance that has exited is replaced.
+pub fn listen(
+	path: &Path,
+	handle: impl Fn(Request, UnixStream) -> bool + Clone + Send + 'static,
+) -> io::Result<()> {
+	if UnixStream::connect(path).is_ok() {
+		return Err(io::Error::new(
+			io::ErrorKind::AddrInUse,
+			"another instance is already listening",
+		));
+	}
+	match std::fs::remove_file(path) {
+		Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+		_ => {}
+	}
+	let listener = UnixListener::bind(path)?;
+
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let Ok(stream) = stream else {
+				continue;
+			};
+			let handle = handle.clone();
+			std::thread::spawn(move || serve(stream, &handle));
+		}
+	});
+	Ok(())
+}
+
+/// Reads requests from one client until it disconnects.
+fn serve(mut stream: UnixStream, handle: &impl Fn(Request, UnixStream) -> bool) -> io::Result<()> {
+	stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+	let lines = BufReader::new(stream.try_clone()?).lines();
+	for line in lines {
+		let line = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		match line.parse() {
+			Ok(request) => {
+				if !handle(request, stream.try_clone()?) {
+					break;
+				}
+			}
+			Err(message) => send(&mut stream, &Reply::Error { message })?,
+		}
+	}
+	Ok(())
+}
+
+/// Writes a reply as a line of JSON.
+pub fn send(stream: &mut impl Write, reply: &Reply) -> io::Result<()> {
+	let mut line = serde_json::to_vec(reply)?;
+	line.push(b'\n');
+	stream.write_all(&line)
+}
+
+/// Sends a request to the running instance at `path`, calling `reply` with each reply until it returns `false`.
+pub fn request(
+	path: &Path,
+	request: Request,
+	mut reply: impl FnMut(Reply) -> bool,
+) -> io::Result<()> {
+	let mut stream = UnixStream::connect(path)?;
+	writeln!(stream, "{request}")?;
+	for line in BufReader::new(stream).lines() {
+		let parsed = serde_json::from_str(&line?)?;
+		if !reply(parsed) {
+			break;
+		}
+	}
+	Ok(())
+}
+
+/// (De)serializes an optional duration as a number of seconds.
+pub mod optional_seconds {
+	use std::time::Duration;
+
+	use serde::{Deserialize, Deserializer, Serializer};
+
+	#[allow(clippy::ref_option)]
+	pub fn serialize<S: Serializer>(
+		duration: &Option<Duration>,
+		serializer: S,
+	) -> Result<S::Ok, S::Error> {
+		match duration {
+			Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+			None => serializer.serialize_none(),
+		}
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Option<Duration>, D::Error> {
+		let seconds = Option::<f64>::deserialize(deserializer)?;
+		seconds
+			.map(|seconds| Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom))
+			.transpose()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn requests() {
+		for line in [
+			"status",
+			"pause",
+			"resume",
+			"inhibit",
+			"inhibit 90",
+			"uninhibit",
+			"reload",
+			"subscribe",
+		] {
+			let request: Request = line.parse().unwrap();
+			assert_eq!(request.to_string(), line);
+		}
+		assert_eq!(
+			"inhibit 1h30m".parse(),
+			Ok(Request::Inhibit(Some(Duration::from_mins(90))))
+		);
+		assert_eq!("force-inhibit".parse(), Ok(Request::Inhibit(None)));
+		assert!("inhibit soon".parse::<Request>().is_err());
+		assert!("status please".parse::<Request>().is_err());
+		assert!("lock".parse::<Request>().is_err());
+	}
+
+	#[test]
+	fn replies() {
+		let status = Reply::Status(Status {
+			inhibited: true,
+			reasons: vec![Reason::Manual],
+			active: false,
+			paused: true,
+			manual: true,
+			manual_for: Some(Duration::from_secs(30)),
+			streams: Vec::new(),
+		});
+		assert_eq!(
+			serde_json::to_string(&status).unwrap(),
+			r#"{"type":"status","inhibited":true,"reasons":["manual"],"active":false,"paused":true,"manual":true,"manual_for":30.0,"streams":[]}"#
+		);
+		assert_eq!(
+			serde_json::to_string(&Reply::Ok).unwrap(),
+			r#"{"type":"ok"}"#
+		);
+		let error: Reply = serde_json::from_str(r#"{"type":"error","message":"no"}"#).unwrap();
+		assert!(matches!(error, Reply::Error { message } if message == "no"));
+	}
+}
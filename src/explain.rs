@@ -0,0 +1,269 @@
+//! Explanations of which streams are holding the inhibitor, and why.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::control::optional_seconds;
+use crate::model::{Facility, Properties, State};
+use crate::policy::{Matcher, Policy, Verdict};
+
+/// When each stream that counts as activity started counting.
+#[derive(Debug, Default)]
+pub struct Activity {
+	since: BTreeMap<(Facility, u32), Instant>,
+}
+
+impl Activity {
+	/// Takes note of the streams that count at `now`. Returns whether they are different ones than before.
+	pub fn update(&mut self, policy: &Policy, state: &State, now: Instant) -> bool {
+		let counting: Vec<_> = counting(policy, state).map(|(key, _)| key).collect();
+		let before = self.since.len();
+		self.since.retain(|key, _| counting.contains(key));
+		let mut changed = self.since.len() != before;
+		for key in counting {
+			self.since.entry(key).or_insert_with(|| {
+				changed = true;
+				now
+			});
+		}
+		changed
+	}
+
+	/// The streams that count as activity. How long they have been counting is only known for those seen by [`Self::update`].
+	pub fn contributions(&self, policy: &Policy, state: &State, now: Instant) -> Vec<Contribution> {
+		counting(policy, state)
+			.map(|(key, contribution)| Contribution {
+				active_for: self.since.get(&key).map(|&since| now - since),
+				..contribution
+			})
+			.collect()
+	}
+}
+
+/// The streams that count, without how long they have been counting.
+fn counting<'a>(
+	policy: &'a Policy,
+	state: &'a State,
+) -> impl Iterator<Item = ((Facility, u32), Contribution)> + 'a {
+	let playback = state.sink_inputs.values().filter_map(|stream| {
+		let Verdict::Counts(rule) = policy.playback_verdict(state, stream) else {
+			return None;
+		};
+		let sink = stream.sink.and_then(|sink| state.sinks.get(&sink));
+		let device = sink.map(|sink| sink.description.as_ref().unwrap_or(&sink.name).clone());
+		let contribution = Contribution::new(
+			StreamKind::Playback,
+			stream.index,
+			&stream.properties,
+			device,
+			rule,
+		);
+		Some(((Facility::SinkInput, stream.index), contribution))
+	});
+	let capture = state.source_outputs.values().filter_map(|stream| {
+		let Verdict::Counts(rule) = policy.capture_verdict(state, stream) else {
+			return None;
+		};
+		let source = stream.source.and_then(|source| state.sources.get(&source));
+		let device = source.map(|source| source.description.as_ref().unwrap_or(&source.name).clone());
+		let contribution = Contribution::new(
+			StreamKind::Capture,
+			stream.index,
+			&stream.properties,
+			device,
+			rule,
+		);
+		Some(((Facility::SourceOutput, stream.index), contribution))
+	});
+	playback.chain(capture)
+}
+
+/// Whether a stream plays or records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamKind {
+	Playback,
+	Capture,
+}
+
+impl Display for StreamKind {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter.write_str(match self {
+			Self::Playback => "playback",
+			Self::Capture => "capture",
+		})
+	}
+}
+
+/// A stream that counts as activity, as people would describe it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contribution {
+	pub kind: StreamKind,
+	pub index: u32,
+	pub application: Option<String>,
+	pub media: Option<String>,
+	pub role: Option<String>,
+	/// The description, or else the name, of the sink or source.
+	pub device: Option<String>,
+	/// How long the stream has been counting, if known.
+	#[serde(with = "optional_seconds")]
+	pub active_for: Option<Duration>,
+	/// The allow rule that the stream matched, if there are any.
+	pub rule: Option<String>,
+}
+
+impl Contribution {
+	fn new(
+		kind: StreamKind,
+		index: u32,
+		properties: &Properties,
+		device: Option<String>,
+		rule: Option<&Matcher>,
+	) -> Self {
+		Self {
+			kind,
+			index,
+			application: application(properties).map(str::to_owned),
+			media: properties.get("media.name").cloned(),
+			role: properties.get("media.role").cloned(),
+			device,
+			active_for: None,
+			rule: rule.map(Matcher::to_string),
+		}
+	}
+}
+
+impl Display for Contribution {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			formatter,
+			"{} {}, {}",
+			self.kind,
+			self.index,
+			self.application.as_deref().unwrap_or("unknown application")
+		)?;
+		if let Some(media) = &self.media {
+			write!(formatter, ": {media}")?;
+		}
+
+		let mut details = Vec::new();
+		if let Some(role) = &self.role {
+			details.push(format!("role {role}"));
+		}
+		if let Some(device) = &self.device {
+			let preposition = match self.kind {
+				StreamKind::Playback => "to",
+				StreamKind::Capture => "from",
+			};
+			details.push(format!("{preposition} {device}"));
+		}
+		// Streams that only just started counting aren't worth timing.
+		if let Some(active_for) = self
+			.active_for
+			.filter(|active_for| active_for.as_secs() > 0)
+		{
+			details.push(format!("for {}", HumanDuration(active_for)));
+		}
+		if let Some(rule) = &self.rule {
+			details.push(format!("allowed by {rule}"));
+		}
+		if !details.is_empty() {
+			write!(formatter, " ({})", details.join(", "))?;
+		}
+		Ok(())
+	}
+}
+
+/// The name of the application that a stream belongs to, if it says.
+pub fn application(properties: &Properties) -> Option<&str> {
+	properties
+		.get("application.name")
+		.or_else(|| properties.get("application.process.binary"))
+		.map(String::as_str)
+}
+
+/// A duration to the second, such as `1h 2m 3s`.
+pub struct HumanDuration(pub Duration);
+
+impl Display for HumanDuration {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		let seconds = self.0.as_secs();
+		let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+		if hours > 0 {
+			write!(formatter, "{hours}h {minutes}m {seconds}s")
+		} else if minutes > 0 {
+			write!(formatter, "{minutes}m {seconds}s")
+		} else {
+			write!(formatter, "{seconds}s")
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::{Sink, SinkInput};
+
+	#[test]
+	fn contributions() {
+		let mut state = State::default();
+		state.sinks.insert(
+			0,
+			Sink {
+				index: 0,
+				name: "alsa_output.hdmi".into(),
+				description: Some("HDMI".into()),
+				properties: Properties::new(),
+				mute: false,
+				volume: Vec::new(),
+				monitor_source: None,
+			},
+		);
+		let properties = [
+			("application.name", "mpv"),
+			("media.name", "song"),
+			("media.role", "music"),
+		]
+		.into_iter()
+		.map(|(key, value)| (key.to_owned(), value.to_owned()))
+		.collect();
+		state.sink_inputs.insert(
+			3,
+			SinkInput {
+				index: 3,
+				client: None,
+				sink: Some(0),
+				properties,
+				corked: false,
+				mute: false,
+				volume: Vec::new(),
+				audible: None,
+			},
+		);
+
+		let mut policy = Policy::default();
+		policy
+			.playback
+			.rules
+			.allow
+			.push("application.name=mpv".parse().unwrap());
+		let mut activity = Activity::default();
+		let start = Instant::now();
+		assert!(activity.update(&policy, &state, start));
+		assert!(!activity.update(&policy, &state, start + Duration::from_secs(1)));
+
+		let contributions = activity.contributions(&policy, &state, start + Duration::from_secs(75));
+		assert_eq!(contributions.len(), 1);
+		assert_eq!(
+			contributions[0].to_string(),
+			"playback 3, mpv: song (role music, to HDMI, for 1m 15s, allowed by application.name=mpv)"
+		);
+
+		state.sink_inputs.get_mut(&3).unwrap().corked = true;
+		assert!(activity.update(&policy, &state, start + Duration::from_secs(2)));
+		assert!(activity.contributions(&policy, &state, start).is_empty());
+	}
+}
@@ -0,0 +1,144 @@
+//! The framing shared by the Pulseaudio and Pipewire native protocols: messages sent over a Unix socket, each a fixed-size header that gives the size of the body after it.
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Instant;
+
+pub fn invalid(message: impl Into<String>) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// How a protocol frames its messages.
+pub struct Framing {
+	/// Who is on the other end, for errors.
+	pub server: &'static str,
+	pub header_size: usize,
+	/// Reads the size of the body from a header.
+	pub body_size: fn(&[u8]) -> usize,
+	pub max_body_size: usize,
+}
+
+pub struct Message {
+	pub header: Vec<u8>,
+	pub body: Vec<u8>,
+}
+
+pub struct Connection {
+	socket: UnixStream,
+	/// Bytes received from the server but not yet parsed into messages.
+	buffer: Vec<u8>,
+	framing: &'static Framing,
+}
+
+impl Connection {
+	pub fn new(socket: UnixStream, framing: &'static Framing) -> Self {
+		Self {
+			socket,
+			buffer: Vec::new(),
+			framing,
+		}
+	}
+
+	pub fn write(&mut self, message: &[u8]) -> io::Result<()> {
+		self.socket.write_all(message)
+	}
+
+	/// Blocks until the next message has arrived.
+	pub fn read(&mut self) -> io::Result<Message> {
+		self.socket.set_read_timeout(None)?;
+		self.read_message()
+	}
+
+	/// Reads the next message, or returns `None` if `deadline` passes first.
+	///
+	/// Partial reads are kept in the buffer, so a message that was cut short by the deadline is picked up again by the next call.
+	pub fn read_until(&mut self, deadline: Option<Instant>) -> io::Result<Option<Message>> {
+		let timeout = match deadline {
+			Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+				Some(remaining) if !remaining.is_zero() => Some(remaining),
+				_ => return Ok(None),
+			},
+			None => None,
+		};
+		self.socket.set_read_timeout(timeout)?;
+		match self.read_message() {
+			Ok(message) => Ok(Some(message)),
+			Err(error)
+				if matches!(
+					error.kind(),
+					io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+				) =>
+			{
+				Ok(None)
+			}
+			Err(error) => Err(error),
+		}
+	}
+
+	fn read_message(&mut self) -> io::Result<Message> {
+		let header_size = self.framing.header_size;
+		loop {
+			if self.buffer.len() >= header_size {
+				let size = (self.framing.body_size)(&self.buffer[..header_size]);
+				if size > self.framing.max_body_size {
+					return Err(invalid(format!("message of {size} bytes is too large")));
+				}
+
+				if self.buffer.len() >= header_size + size {
+					let header = self.buffer[..header_size].to_vec();
+					let body = self.buffer[header_size..][..size].to_vec();
+					self.buffer.drain(..header_size + size);
+					return Ok(Message { header, body });
+				}
+			}
+
+			let mut chunk = [0; 4096];
+			let read = self.socket.read(&mut chunk)?;
+			if read == 0 {
+				return Err(io::Error::new(
+					io::ErrorKind::UnexpectedEof,
+					format!("{} closed the connection", self.framing.server),
+				));
+			}
+			self.buffer.extend_from_slice(&chunk[..read]);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+
+	/// A one-byte header giving the size of the body.
+	const FRAMING: Framing = Framing {
+		server: "test server",
+		header_size: 1,
+		body_size: |header| header[0].into(),
+		max_body_size: 4,
+	};
+
+	#[test]
+	fn partial_messages() {
+		let (socket, mut server) = UnixStream::pair().unwrap();
+		let mut connection = Connection::new(socket, &FRAMING);
+
+		server.write_all(&[3, b'a']).unwrap();
+		let deadline = Instant::now() + Duration::from_millis(10);
+		assert!(connection.read_until(Some(deadline)).unwrap().is_none());
+		server.write_all(b"bc").unwrap();
+		let message = connection.read_until(None).unwrap().unwrap();
+		assert_eq!(message.header, [3]);
+		assert_eq!(message.body, b"abc");
+
+		server.write_all(&[5]).unwrap();
+		let error = connection.read().err().unwrap();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+		drop(server);
+		let mut connection = Connection::new(UnixStream::pair().unwrap().0, &FRAMING);
+		let error = connection.read().err().unwrap();
+		assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+	}
+}
@@ -0,0 +1,121 @@
+//! Why idle is inhibited: a set of reasons, and the targets that inhibit idle while any of them is held.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+/// Why idle is inhibited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+	/// A playback stream counts as activity.
+	Playback,
+	/// A capture stream counts as activity.
+	Capture,
+	/// Someone asked for it with `inhibit`.
+	Manual,
+}
+
+impl Display for Reason {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter.write_str(match self {
+			Self::Playback => "playback",
+			Self::Capture => "capture",
+			Self::Manual => "manual",
+		})
+	}
+}
+
+/// What targets that ask for one are given as the reason for inhibiting idle.
+pub const WHY: &str = "Audio is playing or recording, or idle was inhibited manually";
+
+/// Something that can inhibit idle.
+pub trait Target {
+	fn set_inhibited(&mut self, inhibited: bool);
+}
+
+/// Inhibits idle on every target while any reason is held.
+pub struct Inhibitor {
+	reasons: BTreeSet<Reason>,
+	targets: Vec<Box<dyn Target>>,
+}
+
+impl Inhibitor {
+	pub fn new(targets: Vec<Box<dyn Target>>) -> Self {
+		Self {
+			reasons: BTreeSet::new(),
+			targets,
+		}
+	}
+
+	pub fn is_inhibited(&self) -> bool {
+		!self.reasons.is_empty()
+	}
+
+	/// The reasons that are held.
+	pub fn reasons(&self) -> Vec<Reason> {
+		self.reasons.iter().copied().collect()
+	}
+
+	/// Holds `reason` if `held`, or releases it if not.
+	///
+	/// Returns whether that changed anything.
+	pub fn hold(&mut self, reason: Reason, held: bool) -> bool {
+		let was_inhibited = self.is_inhibited();
+		let changed = if held {
+			self.reasons.insert(reason)
+		} else {
+			self.reasons.remove(&reason)
+		};
+		self.update_targets(was_inhibited);
+		changed
+	}
+
+	fn update_targets(&mut self, was_inhibited: bool) {
+		let inhibited = self.is_inhibited();
+		if inhibited != was_inhibited {
+			for target in &mut self.targets {
+				target.set_inhibited(inhibited);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	use super::*;
+
+	/// Records every change, so the test can check them.
+	struct Recorder(Rc<RefCell<Vec<bool>>>);
+
+	impl Target for Recorder {
+		fn set_inhibited(&mut self, inhibited: bool) {
+			self.0.borrow_mut().push(inhibited);
+		}
+	}
+
+	#[test]
+	fn reasons() {
+		let changes = Rc::new(RefCell::new(Vec::new()));
+		let mut inhibitor = Inhibitor::new(vec![Box::new(Recorder(Rc::clone(&changes)))]);
+		assert!(!inhibitor.is_inhibited());
+
+		assert!(inhibitor.hold(Reason::Playback, true));
+		assert!(!inhibitor.hold(Reason::Playback, true));
+		assert!(inhibitor.hold(Reason::Manual, true));
+		assert_eq!(inhibitor.reasons(), [Reason::Playback, Reason::Manual]);
+
+		assert!(inhibitor.hold(Reason::Playback, false));
+		assert!(inhibitor.is_inhibited());
+		assert!(inhibitor.hold(Reason::Manual, false));
+		assert!(!inhibitor.is_inhibited());
+		assert!(!inhibitor.hold(Reason::Capture, false));
+
+		// The targets only hear about the first reason being held and the last one being released.
+		assert_eq!(*changes.borrow(), [true, false]);
+	}
+}
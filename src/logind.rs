@@ -0,0 +1,221 @@
+//! Inhibiting idle, and sleep, through a systemd-logind inhibitor lock on the system bus. Logind's own idle action doesn't know about the compositor's inhibitors, but honours these.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+
+use serde::Deserialize;
+use zbus::blocking::Connection;
+use zbus::proxy;
+use zbus::zvariant::OwnedFd;
+
+use crate::inhibit::{self, Target};
+
+#[proxy(
+	interface = "org.freedesktop.login1.Manager",
+	default_service = "org.freedesktop.login1",
+	default_path = "/org/freedesktop/login1",
+	gen_async = false,
+	blocking_name = "ManagerProxy"
+)]
+trait Manager {
+	fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+}
+
+/// Something logind can be kept from doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum What {
+	/// Going idle, and so whatever the idle action is.
+	Idle,
+	/// Suspending or hibernating.
+	Sleep,
+	/// Acting on the lid being closed.
+	HandleLidSwitch,
+}
+
+impl Display for What {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter.write_str(match self {
+			Self::Idle => "idle",
+			Self::Sleep => "sleep",
+			Self::HandleLidSwitch => "handle-lid-switch",
+		})
+	}
+}
+
+/// How strongly the lock holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+	/// Keep logind from doing it at all.
+	#[default]
+	Block,
+	/// Only hold it off for a few seconds. Logind only allows this for sleep.
+	Delay,
+}
+
+impl Display for Mode {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter.write_str(match self {
+			Self::Block => "block",
+			Self::Delay => "delay",
+		})
+	}
+}
+
+/// The inhibitor lock to take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lock {
+	pub what: BTreeSet<What>,
+	pub mode: Mode,
+}
+
+impl Default for Lock {
+	fn default() -> Self {
+		Self {
+			what: [What::Idle, What::Sleep].into(),
+			mode: Mode::default(),
+		}
+	}
+}
+
+impl Lock {
+	/// Checks that logind would grant the lock: it has to be for something, and only sleep can be delayed.
+	pub fn check(&self) -> Result<(), String> {
+		if self.what.is_empty() {
+			return Err("the [logind] lock would inhibit nothing, as `what` is empty".into());
+		}
+		if self.mode == Mode::Delay {
+			if let Some(what) = self.what.iter().find(|&&what| what != What::Sleep) {
+				return Err(format!(
+					"logind only allows mode = \"delay\" for sleep, not {what}"
+				));
+			}
+		}
+		Ok(())
+	}
+
+	/// The `what` argument to `Inhibit`, such as `idle:sleep`.
+	fn what(&self) -> String {
+		let what: Vec<_> = self.what.iter().map(What::to_string).collect();
+		what.join(":")
+	}
+}
+
+pub struct Logind {
+	manager: ManagerProxy<'static>,
+	lock: Lock,
+	/// The lock is held for as long as this is open.
+	fd: Option<OwnedFd>,
+}
+
+impl Logind {
+	/// Connects to the system bus.
+	pub fn connect(lock: Lock) -> zbus::Result<Self> {
+		Self::new(&Connection::system()?, lock)
+	}
+
+	pub fn new(connection: &Connection, lock: Lock) -> zbus::Result<Self> {
+		Ok(Self {
+			manager: ManagerProxy::new(connection)?,
+			lock,
+			fd: None,
+		})
+	}
+}
+
+impl Target for Logind {
+	fn set_inhibited(&mut self, inhibited: bool) {
+		if !inhibited {
+			self.fd = None;
+			return;
+		}
+		if self.fd.is_some() {
+			return;
+		}
+		match self.manager.inhibit(
+			&self.lock.what(),
+			env!("CARGO_PKG_NAME"),
+			inhibit::WHY,
+			&self.lock.mode.to_string(),
+		) {
+			Ok(fd) => self.fd = Some(fd),
+			Err(error) => eprintln!("cannot take a logind inhibitor lock: {error}"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{ErrorKind, Read};
+	use std::os::unix::net::UnixStream;
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+
+	use zbus::interface;
+
+	use super::*;
+	use crate::test_bus::{self, TestBus};
+
+	/// The arguments a lock was taken with, and our end of it.
+	type Taken = ([String; 4], UnixStream);
+
+	/// Keeps the other end of every lock it hands out, which is closed when the lock is released.
+	#[derive(Default)]
+	struct Stub {
+		locks: Arc<Mutex<Vec<Taken>>>,
+	}
+
+	#[interface(name = "org.freedesktop.login1.Manager")]
+	impl Stub {
+		fn inhibit(&mut self, what: &str, who: &str, why: &str, mode: &str) -> OwnedFd {
+			let (ours, theirs) = UnixStream::pair().unwrap();
+			let arguments = [what, who, why, mode].map(str::to_owned);
+			self.locks.lock().unwrap().push((arguments, ours));
+			std::os::fd::OwnedFd::from(theirs).into()
+		}
+	}
+
+	/// Whether the other end of a lock has been closed.
+	fn released(stream: &UnixStream) -> bool {
+		stream
+			.set_read_timeout(Some(Duration::from_millis(100)))
+			.unwrap();
+		match (&*stream).read(&mut [0]) {
+			Ok(read) => read == 0,
+			Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => false,
+			Err(error) => panic!("{error}"),
+		}
+	}
+
+	#[test]
+	fn locks() {
+		let Some(bus) = TestBus::start() else {
+			return;
+		};
+		let stub = Stub::default();
+		let locks = Arc::clone(&stub.locks);
+		let _service = bus.serve("org.freedesktop.login1", "/org/freedesktop/login1", stub);
+
+		let lock = Lock {
+			what: [What::HandleLidSwitch, What::Idle].into(),
+			mode: Mode::Block,
+		};
+		let mut logind = Logind::new(&bus.connect(), lock).unwrap();
+		test_bus::check_round_trips(&mut logind, || {
+			let locks = locks.lock().unwrap();
+			locks.iter().filter(|(_, stream)| !released(stream)).count()
+		});
+
+		let (arguments, _) = &locks.lock().unwrap()[0];
+		assert_eq!(
+			arguments,
+			&[
+				"idle:handle-lid-switch",
+				env!("CARGO_PKG_NAME"),
+				inhibit::WHY,
+				"block"
+			]
+		);
+	}
+}
@@ -8,24 +8,60 @@
 	non_ascii_idents,
 	nonstandard_style,
 	noop_method_call,
-	pointer_structural_match,
-	private_in_public,
 	rust_2018_idioms,
 	unused_qualifications
 )]
 #![warn(clippy::pedantic)]
 #![forbid(unsafe_code)]
 
-use std::io::BufRead;
-use std::process::{Command, Stdio};
-use std::sync::mpsc::RecvTimeoutError;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use clap::Parser;
 use wayland_client::protocol::{wl_compositor, wl_registry, wl_surface};
 use wayland_client::{delegate_noop, Connection, Dispatch, Proxy, QueueHandle};
 use wayland_protocols::wp::idle_inhibit::zv1::client::{
 	zwp_idle_inhibit_manager_v1, zwp_idle_inhibitor_v1,
 };
 
+use crate::backend::{AudioSource, Backend};
+use crate::cli::{Cli, Command, SettingsArgs};
+use crate::config::{Settings, Target};
+use crate::control::{Reply, Request, Status};
+use crate::explain::{Activity, Contribution, HumanDuration};
+use crate::inhibit::{Inhibitor, Reason};
+use crate::logind::Logind;
+use crate::model::{Properties, State};
+use crate::policy::{Policy, SilenceDetection, Verdict};
+use crate::portal::Portal;
+use crate::screensaver::ScreenSaver;
+use crate::timing::{Decision, Manual};
+
+mod alsa;
+mod backend;
+mod cli;
+mod config;
+mod control;
+mod explain;
+mod framed;
+mod inhibit;
+mod logind;
+mod mock;
+mod model;
+mod pactl;
+mod pipewire;
+mod policy;
+mod portal;
+mod pulse;
+mod screensaver;
+#[cfg(test)]
+mod test_bus;
+mod timing;
+
 macro_rules! proxies {
 	(struct $name:ident { $($field:ident: $ty:path = $version:tt,)* }) => {
 		#[derive(Default)]
@@ -78,12 +114,54 @@ struct App {
 }
 
 impl App {
-	pub fn is_inhibited(&self) -> bool {
-		self.inhibitor.is_some()
+	/// Connects to the compositor, failing if it doesn't support idle inhibition.
+	pub fn connect() -> Result<Self, String> {
+		let connection = Connection::connect_to_env().map_err(|error| error.to_string())?;
+		let display = connection.display();
+
+		let proxies = {
+			let mut queue = connection.new_event_queue();
+			let handle = queue.handle();
+
+			let _registry = display.get_registry(&handle, ());
+
+			let mut proxies = Proxies {
+				compositor: None,
+				idle_inhibit_manager: None,
+			};
+			queue
+				.roundtrip(&mut proxies)
+				.map_err(|error| error.to_string())?;
+
+			proxies
+		};
+
+		let idle_inhibit_manager = proxies
+			.idle_inhibit_manager
+			.ok_or("the compositor doesn't support idle-inhibit-unstable-v1")?;
+		let compositor = proxies
+			.compositor
+			.ok_or("the compositor has no wl_compositor")?;
+
+		let mut queue = connection.new_event_queue();
+		let handle = queue.handle();
+		let dummy_surface = compositor.create_surface(&handle, ());
+		queue
+			.roundtrip(&mut Ignored)
+			.map_err(|error| error.to_string())?;
+
+		Ok(Self {
+			dummy_surface,
+			manager: idle_inhibit_manager,
+			inhibitor: None,
+			connection,
+		})
 	}
+}
 
-	pub fn set_inhibited(&mut self, inhibited: bool) {
-		if inhibited == self.is_inhibited() {
+impl inhibit::Target for App {
+	fn set_inhibited(&mut self, inhibited: bool) {
+		if inhibited == self.inhibitor.is_some() {
 			return;
 		}
 
@@ -115,87 +193,600 @@ impl<T: Proxy> Dispatch<T, ()> for Ignored {
 	}
 }
 
+/// Something the main loop has to react to.
+enum Event {
+	/// The audio backend's view of the streams and devices changed.
+	Audio(State),
+	/// The audio backend failed, and is being reconnected to.
+	AudioFailed(io::Error),
+	/// A backend has been connected to again after a failure.
+	AudioConnected(Backend),
+	ConfigChanged,
+	/// A request on the control socket, and the connection to reply on.
+	Control(Request, UnixStream),
+}
+
 fn main() {
-	let connection = Connection::connect_to_env().unwrap();
-	let display = connection.display();
+	let cli = Cli::parse();
+	let config_path = cli.config.or_else(config::default_path);
+	let socket_path = cli.socket.or_else(control::default_path);
+	match cli.command.unwrap_or(Command::Run(cli.run)) {
+		Command::Run(args) => run(config_path.as_deref(), socket_path.as_deref(), &args),
+		Command::Status(args) => {
+			if let Some(status) = running_status(socket_path.as_deref()) {
+				print_status(&status);
+				return;
+			}
+			let (backend, policy, state) = snapshot(config_path.as_deref(), &args);
+			let active = if policy.is_active(&state) {
+				"active"
+			} else {
+				"inactive"
+			};
+			println!("audio is {active} (according to the {backend} backend)");
+		}
+		Command::CheckConfig => check_config(config_path.as_deref()),
+		Command::ListStreams(args) => {
+			let (_, policy, state) = snapshot(config_path.as_deref(), &args);
+			for stream in state.sink_inputs.values() {
+				let verdict = policy.playback_verdict(&state, stream);
+				print_stream(
+					"playback",
+					stream.index,
+					stream.client,
+					&stream.properties,
+					verdict,
+				);
+			}
+			for stream in state.source_outputs.values() {
+				let verdict = policy.capture_verdict(&state, stream);
+				print_stream(
+					"capture",
+					stream.index,
+					stream.client,
+					&stream.properties,
+					verdict,
+				);
+			}
+		}
+		Command::Why(args) => {
+			let contributions = running_status(socket_path.as_deref()).map_or_else(
+				|| {
+					let (_, policy, state) = snapshot(config_path.as_deref(), &args);
+					Activity::default().contributions(&policy, &state, Instant::now())
+				},
+				|status| status.streams,
+			);
+			if contributions.is_empty() {
+				println!("no stream counts as activity");
+			}
+			for contribution in contributions {
+				println!("{contribution}");
+			}
+		}
+		Command::Inhibit { duration } => {
+			send_request(socket_path.as_deref(), Request::Inhibit(duration), false);
+		}
+		Command::Uninhibit => send_request(socket_path.as_deref(), Request::Uninhibit, false),
+		Command::Control { request } => {
+			let request = request.join(" ").parse().unwrap_or_else(|error| {
+				eprintln!("{error}");
+				std::process::exit(2);
+			});
+			send_request(socket_path.as_deref(), request, true);
+		}
+	}
+}
 
-	let proxies = {
-		let mut queue = connection.new_event_queue();
-		let handle = queue.handle();
+/// Sends a request to the running instance, exiting with an error if it fails.
+///
+/// If `print` is set, prints the replies as JSON; otherwise only errors are printed.
+fn send_request(socket_path: Option<&Path>, request: Request, print: bool) {
+	let Some(path) = socket_path else {
+		eprintln!("there is no control socket, as $XDG_RUNTIME_DIR is not set");
+		std::process::exit(1);
+	};
+	let mut failed = false;
+	let result = control::request(path, request, |reply| {
+		if print {
+			println!("{}", serde_json::to_string(&reply).unwrap());
+		} else if let Reply::Error { message } = &reply {
+			eprintln!("{message}");
+		}
+		failed |= matches!(reply, Reply::Error { .. });
+		request == Request::Subscribe
+	});
+	if let Err(error) = result {
+		eprintln!("{}: {error}", path.display());
+		std::process::exit(1);
+	}
+	if failed {
+		std::process::exit(1);
+	}
+}
 
-		let _registry = display.get_registry(&handle, ());
+/// Inhibits idle while audio is active, reloading the configuration when it changes.
+fn run(config_path: Option<&Path>, socket_path: Option<&Path>, args: &SettingsArgs) {
+	let settings = load_settings_or_exit(config_path, args);
 
-		let mut proxies = Proxies {
-			compositor: None,
-			idle_inhibit_manager: None,
-		};
-		queue.roundtrip(&mut proxies).unwrap();
+	let mut targets: Vec<Box<dyn inhibit::Target>> = Vec::new();
+	if settings.targets.contains(&Target::Wayland) {
+		match App::connect() {
+			Ok(app) => targets.push(Box::new(app)),
+			Err(error) => eprintln!("cannot connect to the compositor: {error}; skipping"),
+		}
+	}
+	if settings.targets.contains(&Target::ScreenSaver) {
+		match ScreenSaver::connect() {
+			Ok(screen_saver) => targets.push(Box::new(screen_saver)),
+			Err(error) => eprintln!("cannot connect to org.freedesktop.ScreenSaver: {error}; skipping"),
+		}
+	}
+	if settings.targets.contains(&Target::Logind) {
+		match Logind::connect(settings.logind.clone()) {
+			Ok(logind) => targets.push(Box::new(logind)),
+			Err(error) => eprintln!("cannot connect to logind: {error}; skipping"),
+		}
+	}
+	if settings.targets.contains(&Target::Portal) {
+		match Portal::connect(settings.portal) {
+			Ok(portal) => targets.push(Box::new(portal)),
+			Err(error) => eprintln!("cannot connect to the inhibit portal: {error}; skipping"),
+		}
+	}
+	// Without any targets on purpose, we only follow audio; without any that work, we would silently do nothing.
+	if targets.is_empty() && !settings.targets.is_empty() {
+		eprintln!("none of the targets are available");
+		std::process::exit(1);
+	}
 
-		proxies
-	};
+	let (backend, source) = connect_backend_or_exit(&settings);
+	eprintln!("using the {backend} backend");
 
-	let idle_inhibit_manager = proxies
-		.idle_inhibit_manager
-		.expect("no idle inhibit manager");
-	let compositor = proxies.compositor.expect("no compositor");
-
-	let mut queue = connection.new_event_queue();
-	let handle = queue.handle();
-	let dummy_surface = compositor.create_surface(&handle, ());
-	queue.roundtrip(&mut Ignored).unwrap();
-
-	let mut app = App {
-		dummy_surface,
-		manager: idle_inhibit_manager,
-		inhibitor: None,
-		connection,
-	};
+	let (send, events) = mpsc::channel();
+	let debounce = Arc::new(Mutex::new(settings.timings.debounce));
+	{
+		let send = send.clone();
+		let debounce = Arc::clone(&debounce);
+		let preferred = settings.backend;
+		let silence_detection = settings.policy.silence_detection;
+		std::thread::spawn(move || {
+			keep_following_audio(source, preferred, silence_detection, &debounce, &send);
+		});
+	}
+	if let Some(path) = config_path {
+		let send = send.clone();
+		if let Err(error) = config::watch(path, move || send.send(Event::ConfigChanged).is_ok()) {
+			eprintln!("not watching {} for changes: {error}", path.display());
+		}
+	}
+	if let Some(path) = socket_path {
+		let handle = move |request, stream| send.send(Event::Control(request, stream)).is_ok();
+		if let Err(error) = control::listen(path, handle) {
+			eprintln!("not listening on {}: {error}", path.display());
+		}
+	}
 
-	let (update_send, update_recv) = std::sync::mpsc::sync_channel(1);
-	std::thread::spawn(move || 'initial: loop {
-		let Ok(()) = update_recv.recv() else {
-			break 'initial;
+	let mut daemon = Daemon {
+		config_path,
+		args,
+		backend,
+		playback: Decision::new(settings.timings),
+		capture: Decision::new(settings.timings),
+		settings,
+		debounce,
+		state: State::default(),
+		activity: Activity::default(),
+		active: false,
+		paused: false,
+		manual: Manual::Off,
+		changed: false,
+		inhibitor: Inhibitor::new(targets),
+		subscribers: Vec::new(),
+	};
+	loop {
+		let deadline = daemon.step(Instant::now());
+		let event = match deadline {
+			Some(deadline) => {
+				match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+					Ok(event) => event,
+					Err(RecvTimeoutError::Timeout) => continue,
+					Err(RecvTimeoutError::Disconnected) => unreachable!(),
+				}
+			}
+			// The audio thread keeps reconnecting after errors, so it never disconnects.
+			None => events.recv().unwrap(),
 		};
+		daemon.handle(event, Instant::now());
+	}
+}
+
+/// A running instance: what it knows about the audio, and what it has decided.
+#[allow(clippy::struct_excessive_bools)]
+struct Daemon<'a> {
+	config_path: Option<&'a Path>,
+	args: &'a SettingsArgs,
+	backend: Backend,
+	settings: Settings,
+	/// Shared with the thread following the audio.
+	debounce: Arc<Mutex<Duration>>,
+	state: State,
+	/// Whether to inhibit for playback, after the activation delay and linger.
+	playback: Decision,
+	/// Whether to inhibit for capture, after the activation delay and linger.
+	capture: Decision,
+	activity: Activity,
+	/// Whether audio counts as activity.
+	active: bool,
+	/// Whether audio is kept from inhibiting idle.
+	paused: bool,
+	manual: Manual,
+	/// Whether the status has changed in a way that [`Self::step`] can't tell by itself.
+	changed: bool,
+	inhibitor: Inhibitor,
+	/// Connections that asked to be told about every change of status.
+	subscribers: Vec<UnixStream>,
+}
+
+impl Daemon<'_> {
+	/// Decides whether to inhibit at `now`, and returns when to decide again if nothing happens before then.
+	fn step(&mut self, now: Instant) -> Option<Instant> {
+		let mut changed = std::mem::take(&mut self.changed);
+		if self
+			.activity
+			.update(&self.settings.policy, &self.state, now)
+		{
+			log_contributions(
+				&self
+					.activity
+					.contributions(&self.settings.policy, &self.state, now),
+			);
+			changed = true;
+		}
+		let manual_before = self.manual;
+		let manual = self.manual.update(now);
+		if self.manual != manual_before {
+			eprintln!("the manual inhibit has expired");
+			changed = true;
+		}
+
+		let policy = &self.settings.policy;
+		let playback_active = policy.is_playback_active(&self.state);
+		let capture_active = policy.is_capture_active(&self.state);
+		self.active = playback_active || capture_active;
+		let (playback, playback_deadline) = self.playback.update(playback_active, now);
+		let (capture, capture_deadline) = self.capture.update(capture_active, now);
+		changed |= self
+			.inhibitor
+			.hold(Reason::Playback, playback && !self.paused);
+		changed |= self
+			.inhibitor
+			.hold(Reason::Capture, capture && !self.paused);
+		changed |= self.inhibitor.hold(Reason::Manual, manual);
+
+		if changed {
+			let reply = Reply::Status(self.status(now));
+			self
+				.subscribers
+				.retain_mut(|stream| control::send(stream, &reply).is_ok());
+		}
+		[playback_deadline, capture_deadline, self.manual.deadline()]
+			.into_iter()
+			.flatten()
+			.min()
+	}
 
-		'debounced: loop {
-			let res = update_recv.recv_timeout(std::time::Duration::from_secs(1));
-			match res {
-				Ok(()) => {}
-				Err(RecvTimeoutError::Timeout) => break 'debounced,
-				Err(RecvTimeoutError::Disconnected) => break 'initial,
+	fn handle(&mut self, event: Event, now: Instant) {
+		match event {
+			Event::Audio(state) => self.state = state,
+			Event::AudioFailed(error) => {
+				eprintln!("{} backend failed: {error}; reconnecting", self.backend);
+				// Until we hear otherwise, nothing is playing, and there is nothing to linger for.
+				self.state = State::default();
+				self.playback = Decision::new(self.settings.timings);
+				self.capture = Decision::new(self.settings.timings);
+			}
+			Event::AudioConnected(backend) => {
+				eprintln!("using the {backend} backend");
+				self.backend = backend;
+			}
+			Event::ConfigChanged => {
+				if let Err(error) = self.reload() {
+					eprintln!("not reloading: {error}");
+				}
+			}
+			Event::Control(request, mut stream) => {
+				let reply = match request {
+					Request::Status => Reply::Status(self.status(now)),
+					Request::Pause | Request::Resume => {
+						self.paused = request == Request::Pause;
+						self.changed = true;
+						Reply::Ok
+					}
+					Request::Inhibit(duration) => {
+						self.manual = Manual::new(duration, now);
+						self.changed = true;
+						Reply::Ok
+					}
+					Request::Uninhibit => {
+						self.manual = Manual::Off;
+						self.changed = true;
+						Reply::Ok
+					}
+					Request::Reload => match self.reload() {
+						Ok(()) => Reply::Ok,
+						Err(message) => Reply::Error { message },
+					},
+					Request::Subscribe => {
+						let reply = Reply::Status(self.status(now));
+						if control::send(&mut stream, &reply).is_ok() {
+							self.subscribers.push(stream);
+						}
+						return;
+					}
+				};
+				_ = control::send(&mut stream, &reply);
 			}
 		}
+	}
 
-		let any_running = check_uncorked();
-		app.set_inhibited(any_running);
-	});
+	fn status(&self, now: Instant) -> Status {
+		Status {
+			inhibited: self.inhibitor.is_inhibited(),
+			reasons: self.inhibitor.reasons(),
+			active: self.active,
+			paused: self.paused,
+			manual: self.manual != Manual::Off,
+			manual_for: self
+				.manual
+				.deadline()
+				.map(|until| until.saturating_duration_since(now)),
+			streams: self
+				.activity
+				.contributions(&self.settings.policy, &self.state, now),
+		}
+	}
 
-	let audio_events = Command::new("pactl")
-		.arg("subscribe")
-		.stdin(Stdio::null())
-		.stdout(Stdio::piped())
-		.stderr(Stdio::inherit())
-		.spawn()
-		.unwrap();
-	let audio_events = std::io::BufReader::new(audio_events.stdout.unwrap()).lines();
-
-	update_send.send(()).unwrap();
-
-	for event in audio_events {
-		let event = event.unwrap();
-		// Turns out that `pactl subscribe` sends events for when clients connect and disconnect from the bus. We only want change events on sink inputs and source outputs.
-		let Some(on) = event.strip_prefix("Event 'change' on ") else {
-			continue;
+	/// Reloads the configuration file, keeping the settings that only take effect on restart.
+	fn reload(&mut self) -> Result<(), String> {
+		let new = load_settings(self.config_path, self.args)?;
+		let settings = &mut self.settings;
+		if new.backend != settings.backend
+			|| new.targets != settings.targets
+			|| new.logind != settings.logind
+			|| new.portal != settings.portal
+			|| new.policy.silence_detection != settings.policy.silence_detection
+		{
+			eprintln!("changes to the backend, targets, logind lock, portal flags, and silence detection take effect on restart");
+		}
+		settings.policy = Policy {
+			silence_detection: settings.policy.silence_detection,
+			..new.policy
 		};
-		if !on.starts_with("sink-input") && !on.starts_with("source-output") {
-			continue;
+		settings.timings = new.timings;
+		self.playback.set_timings(new.timings);
+		self.capture.set_timings(new.timings);
+		*self.debounce.lock().unwrap() = new.timings.debounce;
+		self.changed = true;
+		eprintln!("reloaded the configuration");
+		Ok(())
+	}
+}
+
+/// Asks the running instance for its status, if there is one.
+fn running_status(socket_path: Option<&Path>) -> Option<Status> {
+	let mut status = None;
+	control::request(socket_path?, Request::Status, |reply| {
+		if let Reply::Status(reply) = reply {
+			status = Some(reply);
 		}
-		update_send.send(()).unwrap();
+		false
+	})
+	.ok()?;
+	status
+}
+
+fn print_status(status: &Status) {
+	let inhibited = if status.reasons.is_empty() {
+		"not inhibited".to_owned()
+	} else {
+		let reasons: Vec<_> = status.reasons.iter().map(Reason::to_string).collect();
+		format!("inhibited for {}", reasons.join(", "))
+	};
+	let mut notes = Vec::new();
+	if status.manual {
+		notes.push(match status.manual_for {
+			Some(manual_for) => format!("manual for another {}", HumanDuration(manual_for)),
+			None => "manual until uninhibited".to_owned(),
+		});
+	}
+	if status.paused {
+		notes.push("audio is paused".to_owned());
 	}
+	let notes = if notes.is_empty() {
+		String::new()
+	} else {
+		format!(" ({})", notes.join(", "))
+	};
+	let active = if status.active { "active" } else { "inactive" };
+	println!("idle is {inhibited}{notes}, and audio is {active}");
+	for contribution in &status.streams {
+		println!("{contribution}");
+	}
+}
+
+fn log_contributions(contributions: &[Contribution]) {
+	if contributions.is_empty() {
+		eprintln!("no stream counts as activity");
+		return;
+	}
+	eprintln!("streams counting as activity:");
+	for contribution in contributions {
+		eprintln!("	{contribution}");
+	}
+}
+
+/// How long to wait for further changes before taking a snapshot.
+const SNAPSHOT_SETTLE: Duration = Duration::from_millis(100);
+
+/// Connects to the backend just long enough to see the current streams and devices.
+fn snapshot(config_path: Option<&Path>, args: &SettingsArgs) -> (Backend, Policy, State) {
+	let settings = load_settings_or_exit(config_path, args);
+	let (backend, mut source) = connect_backend_or_exit(&settings);
+	let silence_detection = settings
+		.policy
+		.silence_detection
+		.filter(|_| backend.detects_silence());
+	match settled_state(source.as_mut(), silence_detection) {
+		Ok(state) => (backend, settings.policy, state),
+		Err(error) => {
+			eprintln!("{backend} backend failed: {error}");
+			std::process::exit(1);
+		}
+	}
+}
+
+/// Some backends only report what they see as events, so give them a moment to do so.
+///
+/// Level meters start out taking every stream for silent, and only change their minds once the level has stayed above the threshold for the whole duration, so with silence detection we wait for that too.
+fn settled_state(
+	source: &mut dyn AudioSource,
+	silence_detection: Option<SilenceDetection>,
+) -> io::Result<State> {
+	while source.wait(Some(SNAPSHOT_SETTLE))? {}
+	if let Some(silence_detection) = silence_detection {
+		// The meters are set up when the state is first asked for.
+		source.state()?;
+		let deadline = Instant::now() + silence_detection.duration + SNAPSHOT_SETTLE;
+		while let Some(left) = deadline
+			.checked_duration_since(Instant::now())
+			.filter(|left| !left.is_zero())
+		{
+			source.wait(Some(left))?;
+		}
+	}
+	source.state().cloned()
+}
+
+fn print_stream(
+	direction: &str,
+	index: u32,
+	client: Option<u32>,
+	properties: &Properties,
+	verdict: Verdict<'_>,
+) {
+	let client = client.map_or(String::new(), |client| format!(" of client {client}"));
+	let application = explain::application(properties).unwrap_or("unknown application");
+	let media = properties
+		.get("media.name")
+		.map_or(String::new(), |media| format!(": {media}"));
+	println!("{direction} {index}{client}, {application}{media} ({verdict})");
+}
+
+fn check_config(path: Option<&Path>) {
+	let Some(path) = path else {
+		println!("there is no configuration file, as neither $XDG_CONFIG_HOME nor $HOME is set");
+		return;
+	};
+	if !path.exists() {
+		println!("{} does not exist, so the defaults apply", path.display());
+		return;
+	}
+	match Settings::load(path) {
+		Ok(_) => println!("{} is valid", path.display()),
+		Err(error) => {
+			eprintln!("{}: {error}", path.display());
+			std::process::exit(1);
+		}
+	}
+}
+
+/// Sends the backend's view of the streams whenever it changes, waiting for bursts of changes to settle first.
+///
+/// Runs on a thread of its own, because most backends can only be waited on by blocking.
+fn follow_audio(
+	mut source: Box<dyn AudioSource>,
+	debounce: &Mutex<Duration>,
+	events: &Sender<Event>,
+) -> io::Result<()> {
+	loop {
+		if events.send(Event::Audio(source.state()?.clone())).is_err() {
+			return Ok(());
+		}
+
+		source.wait(None)?;
+		let debounce = *debounce.lock().unwrap();
+		while source.wait(Some(debounce))? {}
+	}
+}
+
+/// Follows the audio with [`follow_audio`], reconnecting whenever the backend fails, until the main loop stops listening.
+fn keep_following_audio(
+	mut source: Box<dyn AudioSource>,
+	preferred: Option<Backend>,
+	silence_detection: Option<SilenceDetection>,
+	debounce: &Mutex<Duration>,
+	events: &Sender<Event>,
+) {
+	let mut delay = RECONNECT_DELAY;
+	loop {
+		let connected = Instant::now();
+		let Err(error) = follow_audio(source, debounce, events) else {
+			return;
+		};
+		if events.send(Event::AudioFailed(error)).is_err() {
+			return;
+		}
+		// A backend that fails again right after connecting is given longer and longer to recover.
+		if connected.elapsed() > MAX_RECONNECT_DELAY {
+			delay = RECONNECT_DELAY;
+		}
+		let backend;
+		(backend, source) = reconnect(preferred, silence_detection, &mut delay);
+		if events.send(Event::AudioConnected(backend)).is_err() {
+			return;
+		}
+	}
+}
+
+/// How long to wait before reconnecting to the audio backend at first, and at most, as the wait doubles with every failure.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_mins(1);
+
+/// Connects to a backend again after one failed, the same way as at startup, waiting for `delay` before each attempt and doubling it every time.
+fn reconnect(
+	preferred: Option<Backend>,
+	silence_detection: Option<SilenceDetection>,
+	delay: &mut Duration,
+) -> (Backend, Box<dyn AudioSource>) {
+	loop {
+		std::thread::sleep(*delay);
+		*delay = (*delay * 2).min(MAX_RECONNECT_DELAY);
+		if let Some(connected) = backend::connect(preferred, silence_detection) {
+			return connected;
+		}
+	}
+}
+
+fn connect_backend_or_exit(settings: &Settings) -> (Backend, Box<dyn AudioSource>) {
+	backend::connect(settings.backend, settings.policy.silence_detection).unwrap_or_else(|| {
+		eprintln!("no audio backend available");
+		std::process::exit(1);
+	})
+}
+
+fn load_settings(path: Option<&Path>, args: &SettingsArgs) -> Result<Settings, String> {
+	let mut settings = match path {
+		Some(path) => Settings::load(path).map_err(|error| format!("{}: {error}", path.display()))?,
+		None => Settings::default(),
+	};
+	args.apply(&mut settings);
+	Ok(settings)
 }
 
-fn check_uncorked() -> bool {
-	let raw = Command::new("pactl").arg("list").output().unwrap().stdout;
-	let raw = std::str::from_utf8(&raw).unwrap();
-	raw.contains("Corked: no")
+fn load_settings_or_exit(path: Option<&Path>, args: &SettingsArgs) -> Settings {
+	load_settings(path, args).unwrap_or_else(|error| {
+		eprintln!("{error}");
+		std::process::exit(1);
+	})
 }
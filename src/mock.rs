@@ -0,0 +1,99 @@
+//! The mock backend, which reads the activity state from standard input instead of watching a sound server.
+//!
+//! Each line is either `active` or `inactive`. `active` may be followed by `key=value` properties for the stream, such as `active application.name=mpv`.
+//! This is meant for testing the rest of the program.
+
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::backend::AudioSource;
+use crate::model::{Properties, SinkInput, State};
+
+pub struct Mock {
+	state: State,
+	updates: Receiver<Line>,
+}
+
+enum Line {
+	/// A single stream is playing, with these properties.
+	Active(Properties),
+	Inactive,
+}
+
+impl Mock {
+	pub fn new() -> Self {
+		let (send, updates) = mpsc::channel();
+		std::thread::spawn(move || {
+			for line in io::stdin().lock().lines() {
+				let Ok(line) = line else {
+					break;
+				};
+				let Some(update) = parse_line(&line) else {
+					eprintln!("mock backend: expected `active [key=value]...` or `inactive`, got {line:?}");
+					continue;
+				};
+				if send.send(update).is_err() {
+					break;
+				}
+			}
+		});
+
+		Self {
+			state: State::default(),
+			updates,
+		}
+	}
+}
+
+fn parse_line(line: &str) -> Option<Line> {
+	let mut words = line.split_whitespace();
+	match words.next()? {
+		"active" => words
+			.map(|word| {
+				let (key, value) = word.split_once('=')?;
+				Some((key.to_owned(), value.to_owned()))
+			})
+			.collect::<Option<_>>()
+			.map(Line::Active),
+		"inactive" => words.next().is_none().then_some(Line::Inactive),
+		_ => None,
+	}
+}
+
+impl AudioSource for Mock {
+	fn wait(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+		let result = match timeout {
+			Some(timeout) => self.updates.recv_timeout(timeout),
+			None => self.updates.recv().map_err(RecvTimeoutError::from),
+		};
+		match result {
+			Ok(line) => {
+				self.state.sink_inputs.clear();
+				if let Line::Active(properties) = line {
+					let stream = SinkInput {
+						index: 0,
+						client: None,
+						sink: None,
+						properties,
+						corked: false,
+						mute: false,
+						volume: Vec::new(),
+						audible: None,
+					};
+					self.state.sink_inputs.insert(0, stream);
+				}
+				Ok(true)
+			}
+			Err(RecvTimeoutError::Timeout) => Ok(false),
+			Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+				io::ErrorKind::UnexpectedEof,
+				"standard input was closed",
+			)),
+		}
+	}
+
+	fn state(&mut self) -> io::Result<&State> {
+		Ok(&self.state)
+	}
+}
@@ -0,0 +1,161 @@
+//! A typed model of the sound server's streams and devices.
+//!
+//! It follows Pulseaudio's terminology; the other backends map what they see onto it as best they can.
+
+use std::collections::BTreeMap;
+
+pub type Properties = BTreeMap<String, String>;
+
+/// Per-channel volumes, where `VOLUME_NORM` is 100%.
+pub type Volume = Vec<u32>;
+
+#[cfg(test)]
+pub const VOLUME_NORM: u32 = 0x10000;
+
+/// The resampler that the server uses for streams that only want peak levels. Unlike the absence of a resampler, this is never translated by `pactl`.
+pub const PEAK_RESAMPLE_METHOD: &str = "peaks";
+
+/// A playback stream.
+#[derive(Debug, Clone)]
+pub struct SinkInput {
+	pub index: u32,
+	/// The client that owns the stream, as shown by `list-streams` for looking it up in `pactl list clients`.
+	pub client: Option<u32>,
+	/// The index of the sink that the stream plays to, if the backend knows it.
+	pub sink: Option<u32>,
+	pub properties: Properties,
+	pub corked: bool,
+	pub mute: bool,
+	pub volume: Volume,
+	/// Whether the stream is actually making sound, if the backend measures its level.
+	pub audible: Option<bool>,
+}
+
+/// A capture stream.
+#[derive(Debug, Clone)]
+pub struct SourceOutput {
+	pub index: u32,
+	/// The client that owns the stream, as shown by `list-streams` for looking it up in `pactl list clients`.
+	pub client: Option<u32>,
+	/// The index of the source that the stream records from, if the backend knows it.
+	pub source: Option<u32>,
+	pub properties: Properties,
+	pub corked: bool,
+	pub mute: bool,
+	pub volume: Volume,
+	/// Whether the server reduces the stream to peak levels (`PA_STREAM_PEAK_DETECT`), as it does for level meters.
+	pub peak_detect: bool,
+}
+
+/// A playback device.
+#[derive(Debug, Clone)]
+pub struct Sink {
+	pub index: u32,
+	pub name: String,
+	pub description: Option<String>,
+	pub properties: Properties,
+	pub mute: bool,
+	pub volume: Volume,
+	/// The name of the source that monitors this sink.
+	pub monitor_source: Option<String>,
+}
+
+/// A capture device.
+#[derive(Debug, Clone)]
+pub struct Source {
+	pub index: u32,
+	pub name: String,
+	pub description: Option<String>,
+	pub properties: Properties,
+	pub mute: bool,
+	pub volume: Volume,
+	/// The name of the sink this source monitors, if it is a monitor source.
+	pub monitor_of_sink: Option<String>,
+}
+
+/// Whether nothing can be heard at this volume. An empty volume means that it is unknown, which doesn't count.
+fn is_silent(volume: &Volume) -> bool {
+	!volume.is_empty() && volume.iter().all(|&channel| channel == 0)
+}
+
+macro_rules! mutable {
+	($($ty:ty),*) => {
+		$(impl $ty {
+			/// Whether this is muted or at zero volume.
+			pub fn is_muted(&self) -> bool {
+				self.mute || is_silent(&self.volume)
+			}
+		})*
+	};
+}
+
+mutable!(SinkInput, SourceOutput, Sink, Source);
+
+/// Everything we know about the server, keyed by index.
+#[derive(Debug, Default, Clone)]
+pub struct State {
+	pub sink_inputs: BTreeMap<u32, SinkInput>,
+	pub source_outputs: BTreeMap<u32, SourceOutput>,
+	pub sinks: BTreeMap<u32, Sink>,
+	pub sources: BTreeMap<u32, Source>,
+}
+
+impl State {
+	/// Whether the stream records what is being played rather than a real input: from a monitor source, or as a level meter.
+	pub fn is_monitoring(&self, stream: &SourceOutput) -> bool {
+		let on_monitor_source = stream
+			.source
+			.and_then(|source| self.sources.get(&source))
+			.is_some_and(|source| source.monitor_of_sink.is_some());
+		// Pipewire marks streams that capture a sink instead of linking them to a monitor source.
+		let captures_sink = stream
+			.properties
+			.get("stream.capture.sink")
+			.is_some_and(|value| value == "true");
+		stream.peak_detect || on_monitor_source || captures_sink
+	}
+
+	pub fn remove(&mut self, facility: Facility, index: u32) {
+		match facility {
+			Facility::SinkInput => _ = self.sink_inputs.remove(&index),
+			Facility::SourceOutput => _ = self.source_outputs.remove(&index),
+			Facility::Sink => _ = self.sinks.remove(&index),
+			Facility::Source => _ = self.sources.remove(&index),
+		}
+	}
+}
+
+/// Anything that the server identifies by an index.
+pub trait Indexed {
+	fn index(&self) -> u32;
+}
+
+macro_rules! indexed {
+	($($ty:ty),*) => {
+		$(impl Indexed for $ty {
+			fn index(&self) -> u32 {
+				self.index
+			}
+		})*
+	};
+}
+
+indexed!(SinkInput, SourceOutput, Sink, Source);
+
+/// The kinds of object in [`State`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Facility {
+	SinkInput,
+	SourceOutput,
+	Sink,
+	Source,
+}
+
+impl Facility {
+	pub const ALL: [Self; 4] = [
+		Self::SinkInput,
+		Self::SourceOutput,
+		Self::Sink,
+		Self::Source,
+	];
+}
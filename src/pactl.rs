@@ -0,0 +1,580 @@
+//! The `pactl` backend, which follows `pactl subscribe` and re-lists the kinds of object that changed.
+//!
+//! This is the slowest backend, but it works wherever `pactl` does.
+//!
+//! `pactl` translates its output, so it is always run in the C locale, and the server state is read as JSON rather than text.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::backend::AudioSource;
+use crate::model::{
+	Facility, Indexed, Properties, Sink, SinkInput, Source, SourceOutput, State, Volume,
+	PEAK_RESAMPLE_METHOD,
+};
+
+pub struct Pactl {
+	subscription: Child,
+	updates: Receiver<Update>,
+	model: Model,
+}
+
+/// What we know about the server, and which parts of that are out of date.
+struct Model {
+	state: State,
+	/// Kinds of object that have been created or changed since we last listed them.
+	///
+	/// `pactl` can't show a single object by index, so we have to re-list the whole kind, but at least we skip the kinds that didn't change.
+	stale: BTreeSet<Facility>,
+}
+
+impl Pactl {
+	pub fn spawn() -> io::Result<Self> {
+		// `pactl subscribe` would start fine and then exit immediately, so check up front that there is a server to talk to.
+		let info = pactl()
+			.arg("info")
+			.stdin(Stdio::null())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.status()?;
+		if !info.success() {
+			return Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				"pactl could not connect to a server",
+			));
+		}
+
+		let mut subscription = pactl()
+			.arg("subscribe")
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::inherit())
+			.spawn()?;
+		let lines = BufReader::new(subscription.stdout.take().unwrap()).lines();
+
+		let (send, updates) = mpsc::channel();
+		std::thread::spawn(move || {
+			for line in lines {
+				let Ok(line) = line else {
+					break;
+				};
+				let Some(update) = Event::parse(&line).and_then(|event| event.update()) else {
+					continue;
+				};
+				if send.send(update).is_err() {
+					break;
+				}
+			}
+		});
+
+		Ok(Self {
+			subscription,
+			updates,
+			model: Model::default(),
+		})
+	}
+}
+
+impl Default for Model {
+	/// Nothing is known yet, so everything has to be listed.
+	fn default() -> Self {
+		Self {
+			state: State::default(),
+			stale: Facility::ALL.into(),
+		}
+	}
+}
+
+impl Model {
+	fn apply(&mut self, update: Update) {
+		match update {
+			Update::Changed(facility) => _ = self.stale.insert(facility),
+			Update::Removed(facility, index) => self.state.remove(facility, index),
+		}
+	}
+
+	/// Re-lists the kinds of object that are stale, with `list` giving the JSON that `pactl list` prints for a kind such as `sink-inputs`.
+	fn refresh(&mut self, mut list: impl FnMut(&str) -> io::Result<Vec<u8>>) -> io::Result<&State> {
+		for facility in std::mem::take(&mut self.stale) {
+			match facility {
+				Facility::SinkInput => {
+					self.state.sink_inputs = parse_list::<JsonSinkInput, _>(&list("sink-inputs")?)?;
+				}
+				Facility::SourceOutput => {
+					self.state.source_outputs = parse_list::<JsonSourceOutput, _>(&list("source-outputs")?)?;
+				}
+				Facility::Sink => self.state.sinks = parse_list::<JsonSink, _>(&list("sinks")?)?,
+				Facility::Source => {
+					self.state.sources = parse_list::<JsonSource, _>(&list("sources")?)?;
+				}
+			}
+		}
+		Ok(&self.state)
+	}
+}
+
+impl AudioSource for Pactl {
+	fn wait(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+		let result = match timeout {
+			Some(timeout) => self.updates.recv_timeout(timeout),
+			None => self.updates.recv().map_err(RecvTimeoutError::from),
+		};
+		match result {
+			Ok(update) => {
+				self.model.apply(update);
+				while let Ok(update) = self.updates.try_recv() {
+					self.model.apply(update);
+				}
+				Ok(true)
+			}
+			Err(RecvTimeoutError::Timeout) => Ok(false),
+			Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+				io::ErrorKind::UnexpectedEof,
+				"`pactl subscribe` exited",
+			)),
+		}
+	}
+
+	fn state(&mut self) -> io::Result<&State> {
+		self.model.refresh(list)
+	}
+}
+
+impl Drop for Pactl {
+	fn drop(&mut self) {
+		_ = self.subscription.kill();
+		_ = self.subscription.wait();
+	}
+}
+
+/// An event from `pactl subscribe`, such as `Event 'new' on sink-input #42`.
+#[derive(Debug, PartialEq, Eq)]
+struct Event<'a> {
+	/// `new`, `change`, or `remove`.
+	kind: &'a str,
+	/// The kind of object, such as `sink-input` or `client`.
+	facility: &'a str,
+	index: u32,
+}
+
+/// A change to our model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Update {
+	/// An object was created or changed, so its kind needs to be re-listed.
+	Changed(Facility),
+	Removed(Facility, u32),
+}
+
+impl<'a> Event<'a> {
+	fn parse(line: &'a str) -> Option<Self> {
+		let rest = line.strip_prefix("Event '")?;
+		let (kind, rest) = rest.split_once("' on ")?;
+		let (facility, index) = rest.split_once(" #")?;
+		let index = index.trim_end().parse().ok()?;
+		Some(Self {
+			kind,
+			facility,
+			index,
+		})
+	}
+
+	/// The change to our model that this event calls for, if any.
+	///
+	/// Turns out that `pactl subscribe` also sends events for when clients connect and disconnect from the bus, which we don't care about.
+	/// But streams can be created already uncorked and removed without being corked first, so all kinds of events on streams matter, as do changes to the devices they are attached to.
+	fn update(&self) -> Option<Update> {
+		let facility = match self.facility {
+			"sink-input" => Facility::SinkInput,
+			"source-output" => Facility::SourceOutput,
+			"sink" => Facility::Sink,
+			"source" => Facility::Source,
+			_ => return None,
+		};
+		match self.kind {
+			"new" | "change" => Some(Update::Changed(facility)),
+			"remove" => Some(Update::Removed(facility, self.index)),
+			_ => None,
+		}
+	}
+}
+
+fn pactl() -> Command {
+	let mut command = Command::new("pactl");
+	command.env("LC_ALL", "C").env_remove("LANGUAGE");
+	command
+}
+
+/// Lists the objects of one kind, such as `sink-inputs`, as JSON.
+fn list(kind: &str) -> io::Result<Vec<u8>> {
+	let output = pactl()
+		.args(["--format=json", "list", kind])
+		.stdin(Stdio::null())
+		.stderr(Stdio::inherit())
+		.output()?;
+	if !output.status.success() {
+		return Err(io::Error::other(format!(
+			"`pactl list {kind}` failed with {}",
+			output.status
+		)));
+	}
+
+	Ok(output.stdout)
+}
+
+/// Parses a list of objects of one kind into our model.
+fn parse_list<J, T>(json: &[u8]) -> io::Result<BTreeMap<u32, T>>
+where
+	J: DeserializeOwned + Into<T>,
+	T: Indexed,
+{
+	let items: Vec<J> = serde_json::from_slice(json)?;
+	Ok(
+		items
+			.into_iter()
+			.map(|item| {
+				let item = item.into();
+				(item.index(), item)
+			})
+			.collect(),
+	)
+}
+
+/// The volume of each channel, keyed by channel name.
+#[derive(Deserialize)]
+struct JsonVolume(BTreeMap<String, JsonChannelVolume>);
+
+#[derive(Deserialize)]
+struct JsonChannelVolume {
+	value: u32,
+}
+
+impl From<JsonVolume> for Volume {
+	fn from(volume: JsonVolume) -> Self {
+		volume
+			.0
+			.into_values()
+			.map(|channel| channel.value)
+			.collect()
+	}
+}
+
+/// Indices that may be missing are printed as `n/a` rather than omitted.
+fn optional_index(raw: &str) -> Option<u32> {
+	raw.parse().ok()
+}
+
+#[derive(Deserialize)]
+struct JsonSinkInput {
+	index: u32,
+	client: String,
+	sink: u32,
+	#[serde(default)]
+	properties: Properties,
+	corked: bool,
+	mute: bool,
+	volume: JsonVolume,
+}
+
+impl From<JsonSinkInput> for SinkInput {
+	fn from(raw: JsonSinkInput) -> Self {
+		Self {
+			index: raw.index,
+			client: optional_index(&raw.client),
+			sink: Some(raw.sink),
+			properties: raw.properties,
+			corked: raw.corked,
+			mute: raw.mute,
+			volume: raw.volume.into(),
+			audible: None,
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct JsonSourceOutput {
+	index: u32,
+	client: String,
+	source: u32,
+	#[serde(default)]
+	properties: Properties,
+	corked: bool,
+	mute: bool,
+	volume: JsonVolume,
+	resample_method: String,
+}
+
+impl From<JsonSourceOutput> for SourceOutput {
+	fn from(raw: JsonSourceOutput) -> Self {
+		Self {
+			index: raw.index,
+			client: optional_index(&raw.client),
+			source: Some(raw.source),
+			properties: raw.properties,
+			corked: raw.corked,
+			mute: raw.mute,
+			volume: raw.volume.into(),
+			peak_detect: raw.resample_method == PEAK_RESAMPLE_METHOD,
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct JsonSink {
+	index: u32,
+	name: String,
+	description: Option<String>,
+	#[serde(default)]
+	properties: Properties,
+	mute: bool,
+	volume: JsonVolume,
+	monitor_source: Option<String>,
+}
+
+impl From<JsonSink> for Sink {
+	fn from(raw: JsonSink) -> Self {
+		Self {
+			index: raw.index,
+			name: raw.name,
+			description: raw.description,
+			properties: raw.properties,
+			mute: raw.mute,
+			volume: raw.volume.into(),
+			monitor_source: raw.monitor_source,
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct JsonSource {
+	index: u32,
+	name: String,
+	description: Option<String>,
+	#[serde(default)]
+	properties: Properties,
+	mute: bool,
+	volume: JsonVolume,
+	monitor_of_sink: Option<String>,
+}
+
+impl From<JsonSource> for Source {
+	fn from(raw: JsonSource) -> Self {
+		Self {
+			index: raw.index,
+			name: raw.name,
+			description: raw.description,
+			properties: raw.properties,
+			mute: raw.mute,
+			volume: raw.volume.into(),
+			monitor_of_sink: raw.monitor_of_sink.filter(|sink| sink != "n/a"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse<T: for<'de> Deserialize<'de>>(json: &[u8]) -> Vec<T> {
+		serde_json::from_slice(json).unwrap()
+	}
+
+	fn updates(trace: &str) -> Vec<Update> {
+		trace
+			.lines()
+			.filter_map(Event::parse)
+			.filter_map(|event| event.update())
+			.collect()
+	}
+
+	#[test]
+	fn app_start_trace() {
+		let trace = include_str!("../fixtures/pactl/subscribe/app-start.txt");
+		assert_eq!(
+			updates(trace),
+			[
+				Update::Changed(Facility::SinkInput),
+				Update::Changed(Facility::Sink),
+				Update::Changed(Facility::Source),
+				Update::Changed(Facility::SinkInput),
+			],
+		);
+	}
+
+	#[test]
+	fn pause_trace() {
+		let trace = include_str!("../fixtures/pactl/subscribe/pause.txt");
+		assert_eq!(
+			updates(trace),
+			[
+				Update::Changed(Facility::SinkInput),
+				Update::Changed(Facility::Sink),
+			],
+		);
+	}
+
+	#[test]
+	fn app_exit_trace() {
+		let trace = include_str!("../fixtures/pactl/subscribe/app-exit.txt");
+		assert_eq!(
+			updates(trace),
+			[
+				Update::Removed(Facility::SinkInput, 187),
+				Update::Changed(Facility::Sink),
+			],
+		);
+	}
+
+	#[test]
+	fn sink_is_not_sink_input() {
+		assert_eq!(
+			Event::parse("Event 'remove' on sink #3").and_then(|event| event.update()),
+			Some(Update::Removed(Facility::Sink, 3))
+		);
+		assert_eq!(
+			Event::parse("Event 'remove' on sink-input #3").and_then(|event| event.update()),
+			Some(Update::Removed(Facility::SinkInput, 3))
+		);
+		assert_eq!(Event::parse("Got SIGINT, exiting."), None);
+	}
+
+	/// Lists the objects of one kind from the fixtures, noting which kinds were listed.
+	fn list_fixture(listed: &mut Vec<String>, kind: &str) -> Vec<u8> {
+		listed.push(kind.to_owned());
+		match kind {
+			"sink-inputs" => include_bytes!("../fixtures/pactl/sink-inputs.de.json").to_vec(),
+			"source-outputs" => include_bytes!("../fixtures/pactl/source-outputs.fr.json").to_vec(),
+			"sinks" => include_bytes!("../fixtures/pactl/sinks.json").to_vec(),
+			"sources" => include_bytes!("../fixtures/pactl/sources.json").to_vec(),
+			_ => unreachable!("{kind}"),
+		}
+	}
+
+	#[test]
+	fn relists_stale_kinds() {
+		let mut model = Model::default();
+		let mut listed = Vec::new();
+		let state = model
+			.refresh(|kind| Ok(list_fixture(&mut listed, kind)))
+			.unwrap();
+		assert_eq!(state.sink_inputs.len(), 2);
+		assert_eq!(state.source_outputs.len(), 1);
+		assert_eq!(
+			listed,
+			["sink-inputs", "source-outputs", "sinks", "sources"]
+		);
+
+		// Each kind that changed is listed once, however many events there were, and removals need no listing.
+		let sink_input = *model.state.sink_inputs.keys().next().unwrap();
+		let sink = *model.state.sinks.keys().next().unwrap();
+		for update in updates(include_str!("../fixtures/pactl/subscribe/pause.txt")) {
+			model.apply(update);
+		}
+		model.apply(Update::Changed(Facility::SinkInput));
+		model.apply(Update::Removed(Facility::SinkInput, sink_input));
+		model.apply(Update::Removed(Facility::Sink, sink));
+		assert!(!model.state.sinks.contains_key(&sink));
+
+		listed.clear();
+		model
+			.refresh(|kind| Ok(list_fixture(&mut listed, kind)))
+			.unwrap();
+		assert_eq!(listed, ["sink-inputs", "sinks"]);
+		// Listing brings back whatever is still there.
+		assert!(model.state.sink_inputs.contains_key(&sink_input));
+		assert!(model.state.sinks.contains_key(&sink));
+
+		listed.clear();
+		model.apply(Update::Removed(Facility::SourceOutput, 7));
+		model
+			.refresh(|kind| Ok(list_fixture(&mut listed, kind)))
+			.unwrap();
+		assert!(listed.is_empty());
+	}
+
+	#[test]
+	fn child_runs_in_c_locale() {
+		let command = pactl();
+		let envs: Vec<_> = command.get_envs().collect();
+		assert!(envs.contains(&("LC_ALL".as_ref(), Some("C".as_ref()))));
+		assert!(envs.contains(&("LANGUAGE".as_ref(), None)));
+	}
+
+	#[test]
+	fn german_sink_inputs() {
+		let streams: Vec<SinkInput> =
+			parse::<JsonSinkInput>(include_bytes!("../fixtures/pactl/sink-inputs.de.json"))
+				.into_iter()
+				.map(Into::into)
+				.collect();
+		assert_eq!(streams.len(), 2);
+		assert!(!streams[0].corked);
+		assert!(streams[1].corked);
+		assert_eq!(streams[0].client, Some(88));
+		assert_eq!(streams[1].volume, [52429, 52429]);
+		assert_eq!(streams[1].properties["application.process.binary"], "mpv");
+	}
+
+	#[test]
+	fn no_streams() {
+		let streams = parse_list::<JsonSinkInput, SinkInput>(b"[]").unwrap();
+		assert!(streams.is_empty());
+	}
+
+	#[test]
+	fn french_sink_inputs() {
+		let streams = parse::<JsonSinkInput>(include_bytes!("../fixtures/pactl/sink-inputs.fr.json"));
+		assert!(streams.iter().all(|stream| stream.corked));
+	}
+
+	#[test]
+	fn french_source_outputs() {
+		let streams: Vec<SourceOutput> =
+			parse::<JsonSourceOutput>(include_bytes!("../fixtures/pactl/source-outputs.fr.json"))
+				.into_iter()
+				.map(Into::into)
+				.collect();
+		assert_eq!(streams.len(), 1);
+		assert!(!streams[0].corked);
+		assert_eq!(streams[0].source, Some(2));
+		assert!(!streams[0].peak_detect);
+	}
+
+	#[test]
+	fn peak_meter() {
+		let streams: Vec<SourceOutput> = parse::<JsonSourceOutput>(include_bytes!(
+			"../fixtures/pactl/source-outputs.meter.json"
+		))
+		.into_iter()
+		.map(Into::into)
+		.collect();
+		assert!(streams[0].peak_detect);
+	}
+
+	#[test]
+	fn sinks_and_sources() {
+		let sinks: Vec<Sink> = parse::<JsonSink>(include_bytes!("../fixtures/pactl/sinks.json"))
+			.into_iter()
+			.map(Into::into)
+			.collect();
+		let sources: Vec<Source> =
+			parse::<JsonSource>(include_bytes!("../fixtures/pactl/sources.json"))
+				.into_iter()
+				.map(Into::into)
+				.collect();
+		assert_eq!(
+			sinks[0].monitor_source.as_deref(),
+			Some("alsa_output.pci-0000_00_1f.3.analog-stereo.monitor")
+		);
+		assert_eq!(
+			sources[0].monitor_of_sink.as_deref(),
+			Some("alsa_output.pci-0000_00_1f.3.analog-stereo")
+		);
+		assert_eq!(sources[1].monitor_of_sink, None);
+	}
+}
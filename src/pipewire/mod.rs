@@ -0,0 +1,486 @@
+//! A client for the Pipewire native protocol, as spoken over the core socket.
+//!
+//! We bind every audio stream node that appears in the registry and follow its state, which is `running` while the stream is playing or recording, and its properties.
+//! This works without pipewire-pulse.
+//!
+//! Output streams are reported as sink inputs and input streams as source outputs, keyed by global ID. Which device a stream is linked to, and its volume, are not followed.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use self::pod::{Builder, Parser, Properties};
+use crate::backend::AudioSource;
+use crate::framed::{invalid, Connection, Framing, Message};
+use crate::model::{SinkInput, SourceOutput, State};
+
+mod pod;
+
+const PROTOCOL_VERSION: i32 = 3;
+const NODE_VERSION: i32 = 3;
+const NODE_INTERFACE: &str = "PipeWire:Interface:Node";
+/// The media classes of the nodes created for application streams.
+const PLAYBACK_MEDIA_CLASS: &str = "Stream/Output/Audio";
+const CAPTURE_MEDIA_CLASS: &str = "Stream/Input/Audio";
+
+const HEADER_SIZE: usize = 16;
+/// Matches the server's own limit on message size.
+const MAX_MESSAGE_SIZE: usize = 0x00ff_ffff;
+const FRAMING: Framing = Framing {
+	server: "Pipewire server",
+	header_size: HEADER_SIZE,
+	body_size: |header| (u32::from_ne_bytes(header[4..8].try_into().unwrap()) & 0x00ff_ffff) as usize,
+	max_body_size: MAX_MESSAGE_SIZE,
+};
+
+const CORE_ID: u32 = 0;
+const CLIENT_ID: u32 = 1;
+const REGISTRY_ID: u32 = 2;
+
+mod core {
+	pub const HELLO: u8 = 1;
+	pub const SYNC: u8 = 2;
+	pub const PONG: u8 = 3;
+	pub const GET_REGISTRY: u8 = 5;
+
+	pub const EVENT_DONE: u8 = 1;
+	pub const EVENT_PING: u8 = 2;
+	pub const EVENT_ERROR: u8 = 3;
+}
+
+mod client {
+	pub const UPDATE_PROPERTIES: u8 = 2;
+}
+
+mod registry {
+	pub const BIND: u8 = 1;
+
+	pub const EVENT_GLOBAL: u8 = 0;
+	pub const EVENT_GLOBAL_REMOVE: u8 = 1;
+}
+
+mod node {
+	pub const EVENT_INFO: u8 = 0;
+
+	pub const CHANGE_MASK_STATE: i64 = 1 << 2;
+	pub const CHANGE_MASK_PROPS: i64 = 1 << 3;
+
+	pub const STATE_RUNNING: u32 = 3;
+}
+
+/// An application stream node.
+#[derive(Debug)]
+struct Stream {
+	/// The ID of our proxy for the node.
+	proxy: u32,
+	/// Whether the stream records rather than plays.
+	capture: bool,
+	running: bool,
+	properties: Properties,
+}
+
+pub struct Client {
+	connection: Connection,
+	next_seq: u32,
+	/// The ID for the next proxy we create. We never reuse IDs, so the server's map of our objects only ever grows at the end.
+	next_proxy: u32,
+	/// Set when a stream has changed in a way that has not yet been reported by [`Self::wait`].
+	notified: bool,
+	/// Application stream nodes by global ID.
+	streams: BTreeMap<u32, Stream>,
+	/// Rebuilt from `streams` whenever it is asked for.
+	state: State,
+}
+
+impl Client {
+	pub fn connect() -> io::Result<Self> {
+		let path = socket_path()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no Pipewire socket found"))?;
+		let socket = UnixStream::connect(path)?;
+
+		let mut client = Self {
+			connection: Connection::new(socket, &FRAMING),
+			next_seq: 0,
+			next_proxy: REGISTRY_ID + 1,
+			notified: false,
+			streams: BTreeMap::new(),
+			state: State::default(),
+		};
+
+		client.send(CORE_ID, core::HELLO, |fields| {
+			fields.int(PROTOCOL_VERSION);
+		})?;
+		let properties = Properties::from([
+			("application.name".into(), env!("CARGO_PKG_NAME").into()),
+			(
+				"application.process.id".into(),
+				std::process::id().to_string(),
+			),
+		]);
+		client.send(CLIENT_ID, client::UPDATE_PROPERTIES, |fields| {
+			fields.properties(&properties);
+		})?;
+		client.send(CORE_ID, core::GET_REGISTRY, |fields| {
+			fields.int(PROTOCOL_VERSION).int(id_to_int(REGISTRY_ID));
+		})?;
+
+		// The first round trip gets us all existing globals, which we bind as they arrive. The second gets us the initial state of those nodes.
+		client.roundtrip()?;
+		client.roundtrip()?;
+		client.notified = false;
+
+		Ok(client)
+	}
+
+	/// Blocks until the server has processed everything we have sent so far, handling events in the meantime.
+	fn roundtrip(&mut self) -> io::Result<()> {
+		let seq = self.next_seq;
+		self.send(CORE_ID, core::SYNC, |fields| {
+			fields.int(id_to_int(CORE_ID)).int(id_to_int(seq));
+		})?;
+
+		loop {
+			let (id, opcode, body) = split(self.connection.read()?);
+			if id == CORE_ID && opcode == core::EVENT_DONE {
+				let mut fields = Parser::message(&body)?;
+				let _id = fields.int()?;
+				if int_to_id(fields.int()?) == seq {
+					return Ok(());
+				}
+			}
+			self.handle(id, opcode, &body)?;
+		}
+	}
+
+	fn handle(&mut self, id: u32, opcode: u8, body: &[u8]) -> io::Result<()> {
+		let mut fields = Parser::message(body)?;
+		match (id, opcode) {
+			(CORE_ID, core::EVENT_PING) => {
+				let ping_id = fields.int()?;
+				let seq = fields.int()?;
+				self.send(CORE_ID, core::PONG, |fields| {
+					fields.int(ping_id).int(seq);
+				})?;
+			}
+			(CORE_ID, core::EVENT_ERROR) => {
+				let object = int_to_id(fields.int()?);
+				let _seq = fields.int()?;
+				let result = fields.int()?;
+				let message = fields.string()?.unwrap_or_default();
+				if object == CORE_ID {
+					return Err(io::Error::other(format!(
+						"Pipewire server reported error {result}: {message}"
+					)));
+				}
+			}
+			(REGISTRY_ID, registry::EVENT_GLOBAL) => {
+				let global = int_to_id(fields.int()?);
+				let _permissions = fields.int()?;
+				let interface = fields.string()?;
+				let _version = fields.int()?;
+				let properties = fields.properties()?;
+
+				let capture = match properties.get("media.class").map(String::as_str) {
+					Some(PLAYBACK_MEDIA_CLASS) => false,
+					Some(CAPTURE_MEDIA_CLASS) => true,
+					_ => return Ok(()),
+				};
+				if interface == Some(NODE_INTERFACE) {
+					self.bind(global, capture, properties)?;
+				}
+			}
+			(REGISTRY_ID, registry::EVENT_GLOBAL_REMOVE) => {
+				let global = int_to_id(fields.int()?);
+				if let Some(stream) = self.streams.remove(&global) {
+					self.notified |= stream.running;
+				}
+			}
+			(proxy, node::EVENT_INFO) if proxy > REGISTRY_ID => {
+				let global = int_to_id(fields.int()?);
+				let _max_input_ports = fields.int()?;
+				let _max_output_ports = fields.int()?;
+				let change_mask = fields.long()?;
+				let _input_ports = fields.int()?;
+				let _output_ports = fields.int()?;
+				let state = fields.id()?;
+				let _error = fields.string()?;
+				let properties = fields.properties()?;
+
+				let Some(stream) = self
+					.streams
+					.get_mut(&global)
+					.filter(|stream| stream.proxy == proxy)
+				else {
+					return Ok(());
+				};
+				if change_mask & node::CHANGE_MASK_STATE != 0 {
+					let running = state == node::STATE_RUNNING;
+					self.notified |= running != stream.running;
+					stream.running = running;
+				}
+				if change_mask & node::CHANGE_MASK_PROPS != 0 && properties != stream.properties {
+					self.notified = true;
+					stream.properties = properties;
+				}
+			}
+			_ => {}
+		}
+
+		Ok(())
+	}
+
+	/// Binds a stream node, starting with the properties from the registry until the node tells us the rest.
+	fn bind(&mut self, global: u32, capture: bool, properties: Properties) -> io::Result<()> {
+		let proxy = self.next_proxy;
+		self.next_proxy += 1;
+		self.send(REGISTRY_ID, registry::BIND, |fields| {
+			fields
+				.int(id_to_int(global))
+				.string(NODE_INTERFACE)
+				.int(NODE_VERSION)
+				.int(id_to_int(proxy));
+		})?;
+		self.streams.insert(
+			global,
+			Stream {
+				proxy,
+				capture,
+				running: false,
+				properties,
+			},
+		);
+		Ok(())
+	}
+
+	fn send(&mut self, id: u32, opcode: u8, fields: impl FnOnce(&mut Builder)) -> io::Result<()> {
+		let mut body = Builder::default();
+		body.structure(fields);
+		let body = body.into_bytes();
+		if body.len() > MAX_MESSAGE_SIZE {
+			return Err(invalid("message too large"));
+		}
+
+		let seq = self.next_seq;
+		self.next_seq = self.next_seq.wrapping_add(1);
+
+		let mut message = Vec::with_capacity(HEADER_SIZE + body.len());
+		#[allow(clippy::cast_possible_truncation)] // Checked above.
+		let size = body.len() as u32;
+		for field in [id, u32::from(opcode) << 24 | size, seq, 0] {
+			message.extend_from_slice(&field.to_ne_bytes());
+		}
+		message.extend_from_slice(&body);
+		self.connection.write(&message)
+	}
+}
+
+impl AudioSource for Client {
+	/// Waits until a stream node appears, disappears, or changes state, or until `timeout` elapses.
+	///
+	/// Returns whether a change occurred.
+	fn wait(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			if std::mem::take(&mut self.notified) {
+				return Ok(true);
+			}
+
+			let Some(message) = self.connection.read_until(deadline)? else {
+				return Ok(false);
+			};
+			let (id, opcode, body) = split(message);
+			self.handle(id, opcode, &body)?;
+		}
+	}
+
+	/// Our model is kept up to date as events arrive, so this never has to ask the server.
+	fn state(&mut self) -> io::Result<&State> {
+		let mut state = State::default();
+		for (&index, stream) in &self.streams {
+			let client = stream
+				.properties
+				.get("client.id")
+				.and_then(|id| id.parse().ok());
+			let properties = stream.properties.clone();
+			let corked = !stream.running;
+			if stream.capture {
+				let stream = SourceOutput {
+					index,
+					client,
+					source: None,
+					properties,
+					corked,
+					mute: false,
+					volume: Vec::new(),
+					peak_detect: false,
+				};
+				state.source_outputs.insert(index, stream);
+			} else {
+				let stream = SinkInput {
+					index,
+					client,
+					sink: None,
+					properties,
+					corked,
+					mute: false,
+					volume: Vec::new(),
+					audible: None,
+				};
+				state.sink_inputs.insert(index, stream);
+			}
+		}
+		self.state = state;
+		Ok(&self.state)
+	}
+}
+
+/// IDs are unsigned, but the protocol sends them as signed integers.
+fn id_to_int(id: u32) -> i32 {
+	i32::from_ne_bytes(id.to_ne_bytes())
+}
+
+fn int_to_id(int: i32) -> u32 {
+	u32::from_ne_bytes(int.to_ne_bytes())
+}
+
+/// Splits a message into the ID of the object it is for, its opcode, and its body.
+fn split(Message { header, body }: Message) -> (u32, u8, Vec<u8>) {
+	let id = u32::from_ne_bytes(header[..4].try_into().unwrap());
+	#[allow(clippy::cast_possible_truncation)] // The opcode is the top eight bits.
+	let opcode = (u32::from_ne_bytes(header[4..8].try_into().unwrap()) >> 24) as u8;
+	(id, opcode, body)
+}
+
+/// Finds the core socket the same way libpipewire does: `$PIPEWIRE_REMOTE`, which may be a name or a path, defaulting to `pipewire-0` in the runtime directory.
+fn socket_path() -> Option<PathBuf> {
+	let remote = std::env::var_os("PIPEWIRE_REMOTE").unwrap_or_else(|| "pipewire-0".into());
+	let remote = PathBuf::from(remote);
+	if remote.is_absolute() {
+		return Some(remote);
+	}
+
+	let runtime_dir =
+		std::env::var_os("PIPEWIRE_RUNTIME_DIR").or_else(|| std::env::var_os("XDG_RUNTIME_DIR"))?;
+	Some(PathBuf::from(runtime_dir).join(remote))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Read;
+
+	use super::*;
+
+	/// A client whose server is the returned end of a socket pair, for feeding events to by hand.
+	fn client() -> (Client, UnixStream) {
+		let (socket, server) = UnixStream::pair().unwrap();
+		let client = Client {
+			connection: Connection::new(socket, &FRAMING),
+			next_seq: 0,
+			next_proxy: REGISTRY_ID + 1,
+			notified: false,
+			streams: BTreeMap::new(),
+			state: State::default(),
+		};
+		(client, server)
+	}
+
+	fn body(fields: impl FnOnce(&mut Builder)) -> Vec<u8> {
+		let mut body = Builder::default();
+		body.structure(fields);
+		body.into_bytes()
+	}
+
+	#[test]
+	fn node_info() {
+		let (mut client, mut server) = client();
+		let global = 57;
+
+		let properties = Properties::from([
+			("media.class".into(), PLAYBACK_MEDIA_CLASS.into()),
+			("application.name".into(), "mpv".into()),
+		]);
+		let event = body(|fields| {
+			fields
+				.int(id_to_int(global))
+				.int(0x1ff)
+				.string(NODE_INTERFACE)
+				.int(NODE_VERSION)
+				.properties(&properties);
+		});
+		client
+			.handle(REGISTRY_ID, registry::EVENT_GLOBAL, &event)
+			.unwrap();
+		assert!(client.state().unwrap().sink_inputs[&global].corked);
+		assert!(!client.notified);
+
+		// We asked the server to bind the node to our next proxy.
+		let mut header = [0; HEADER_SIZE];
+		server.read_exact(&mut header).unwrap();
+		assert_eq!(header[..4], REGISTRY_ID.to_ne_bytes());
+		assert_eq!(header[7], registry::BIND);
+		let proxy = REGISTRY_ID + 1;
+
+		let info = |state: u32, change_mask: i64, media: &str| {
+			let mut properties = properties.clone();
+			properties.insert("media.name".into(), media.into());
+			body(|fields| {
+				fields
+					.int(id_to_int(global))
+					.int(0)
+					.int(1)
+					.long(change_mask)
+					.int(0)
+					.int(1)
+					.id(state)
+					.none()
+					.properties(&properties);
+			})
+		};
+
+		// Without the state in the change mask, the state is ignored.
+		client
+			.handle(
+				proxy,
+				node::EVENT_INFO,
+				&info(node::STATE_RUNNING, 0, "song"),
+			)
+			.unwrap();
+		assert!(client.state().unwrap().sink_inputs[&global].corked);
+		assert!(!client.notified);
+
+		let change_mask = node::CHANGE_MASK_STATE | node::CHANGE_MASK_PROPS;
+		client
+			.handle(
+				proxy,
+				node::EVENT_INFO,
+				&info(node::STATE_RUNNING, change_mask, "song"),
+			)
+			.unwrap();
+		assert!(client.notified);
+		let state = client.state().unwrap();
+		let stream = &state.sink_inputs[&global];
+		assert!(!stream.corked);
+		assert_eq!(stream.properties["media.name"], "song");
+
+		// Events for another proxy are not about our node.
+		client.notified = false;
+		client
+			.handle(proxy + 1, node::EVENT_INFO, &info(2, change_mask, "song"))
+			.unwrap();
+		assert!(!client.notified);
+
+		client
+			.handle(
+				REGISTRY_ID,
+				registry::EVENT_GLOBAL_REMOVE,
+				&body(|fields| {
+					fields.int(id_to_int(global));
+				}),
+			)
+			.unwrap();
+		assert!(client.notified);
+		assert!(client.state().unwrap().sink_inputs.is_empty());
+	}
+}
@@ -0,0 +1,248 @@
+//! The "plain old data" format used for the arguments of every message in the Pipewire native protocol.
+//!
+//! Each value is a size, a type, and a body padded to eight bytes, all in host byte order.
+
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::framed::invalid;
+
+mod kind {
+	pub const NONE: u32 = 1;
+	pub const ID: u32 = 3;
+	pub const INT: u32 = 4;
+	pub const LONG: u32 = 5;
+	pub const STRING: u32 = 8;
+	pub const STRUCT: u32 = 14;
+}
+
+const HEADER_SIZE: usize = 8;
+const ALIGNMENT: usize = 8;
+
+/// A dictionary of properties, as attached to globals and objects.
+pub type Properties = BTreeMap<String, String>;
+
+fn padded(len: usize) -> usize {
+	len.next_multiple_of(ALIGNMENT)
+}
+
+#[derive(Default)]
+pub struct Builder {
+	data: Vec<u8>,
+}
+
+impl Builder {
+	pub fn into_bytes(self) -> Vec<u8> {
+		self.data
+	}
+
+	fn value(&mut self, kind: u32, body: &[u8]) -> &mut Self {
+		let size: u32 = body.len().try_into().expect("pod too large");
+		self.data.extend_from_slice(&size.to_ne_bytes());
+		self.data.extend_from_slice(&kind.to_ne_bytes());
+		self.data.extend_from_slice(body);
+		self.data.resize(padded(self.data.len()), 0);
+		self
+	}
+
+	pub fn int(&mut self, value: i32) -> &mut Self {
+		self.value(kind::INT, &value.to_ne_bytes())
+	}
+
+	pub fn string(&mut self, value: &str) -> &mut Self {
+		let mut body = value.as_bytes().to_vec();
+		body.push(0);
+		self.value(kind::STRING, &body)
+	}
+
+	pub fn structure(&mut self, fields: impl FnOnce(&mut Builder)) -> &mut Self {
+		let mut inner = Builder::default();
+		fields(&mut inner);
+		self.value(kind::STRUCT, &inner.data)
+	}
+
+	pub fn properties(&mut self, properties: &Properties) -> &mut Self {
+		self.structure(|fields| {
+			fields.int(properties.len().try_into().expect("too many properties"));
+			for (key, value) in properties {
+				fields.string(key).string(value);
+			}
+		})
+	}
+
+	#[cfg(test)]
+	pub fn none(&mut self) -> &mut Self {
+		self.value(kind::NONE, &[])
+	}
+
+	#[cfg(test)]
+	pub fn long(&mut self, value: i64) -> &mut Self {
+		self.value(kind::LONG, &value.to_ne_bytes())
+	}
+
+	#[cfg(test)]
+	pub fn id(&mut self, value: u32) -> &mut Self {
+		self.value(kind::ID, &value.to_ne_bytes())
+	}
+}
+
+/// Reads the fields of a struct in order.
+pub struct Parser<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Parser<'a> {
+	/// Parses a message body, which is a single struct.
+	pub fn message(data: &'a [u8]) -> io::Result<Self> {
+		Self { data }.structure()
+	}
+
+	fn value(&mut self) -> io::Result<(u32, &'a [u8])> {
+		if self.data.len() < HEADER_SIZE {
+			return Err(invalid("truncated pod header"));
+		}
+		let word = |index: usize| u32::from_ne_bytes(self.data[index * 4..][..4].try_into().unwrap());
+		let size = word(0) as usize;
+		let kind = word(1);
+		let body = self.data[HEADER_SIZE..]
+			.get(..size)
+			.ok_or_else(|| invalid("truncated pod body"))?;
+		let consumed = padded(HEADER_SIZE + size).min(self.data.len());
+		self.data = &self.data[consumed..];
+		Ok((kind, body))
+	}
+
+	fn expect(&mut self, expected: u32) -> io::Result<&'a [u8]> {
+		let (kind, body) = self.value()?;
+		if kind == expected {
+			Ok(body)
+		} else {
+			Err(invalid(format!(
+				"expected pod of type {expected} but got {kind}"
+			)))
+		}
+	}
+
+	fn word<const N: usize>(body: &[u8]) -> io::Result<[u8; N]> {
+		body
+			.get(..N)
+			.and_then(|body| body.try_into().ok())
+			.ok_or_else(|| invalid("pod body too small"))
+	}
+
+	pub fn int(&mut self) -> io::Result<i32> {
+		Ok(i32::from_ne_bytes(Self::word(self.expect(kind::INT)?)?))
+	}
+
+	pub fn long(&mut self) -> io::Result<i64> {
+		Ok(i64::from_ne_bytes(Self::word(self.expect(kind::LONG)?)?))
+	}
+
+	pub fn id(&mut self) -> io::Result<u32> {
+		Ok(u32::from_ne_bytes(Self::word(self.expect(kind::ID)?)?))
+	}
+
+	/// Reads a string, which may also be sent as "none" to represent null.
+	pub fn string(&mut self) -> io::Result<Option<&'a str>> {
+		match self.value()? {
+			(kind::NONE, _) => Ok(None),
+			(kind::STRING, body) => {
+				let body = body.split(|&byte| byte == 0).next().unwrap_or_default();
+				std::str::from_utf8(body)
+					.map(Some)
+					.map_err(|_| invalid("string is not UTF-8"))
+			}
+			(kind, _) => Err(invalid(format!(
+				"expected a string but got pod of type {kind}"
+			))),
+		}
+	}
+
+	pub fn structure(&mut self) -> io::Result<Parser<'a>> {
+		let data = self.expect(kind::STRUCT)?;
+		Ok(Parser { data })
+	}
+
+	pub fn properties(&mut self) -> io::Result<Properties> {
+		let mut fields = self.structure()?;
+		let len = fields.int()?;
+		(0..len)
+			.map(|_| {
+				let key = fields.string()?.unwrap_or_default().to_owned();
+				let value = fields.string()?.unwrap_or_default().to_owned();
+				Ok((key, value))
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn properties() -> Properties {
+		Properties::from([
+			("media.class".into(), "Stream/Output/Audio".into()),
+			("node.name".into(), "mpv".into()),
+		])
+	}
+
+	/// A message body with one of each kind, and strings of awkward lengths to check the padding.
+	fn message() -> Vec<u8> {
+		let mut builder = Builder::default();
+		builder.structure(|fields| {
+			fields
+				.int(-1)
+				.string("")
+				.string("seven!!")
+				.string("eight!!!")
+				.none()
+				.long(i64::MIN)
+				.id(3)
+				.properties(&properties())
+				.int(42);
+		});
+		builder.into_bytes()
+	}
+
+	fn parse_message(data: &[u8]) -> io::Result<()> {
+		let mut fields = Parser::message(data)?;
+		assert_eq!(fields.int()?, -1);
+		assert_eq!(fields.string()?, Some(""));
+		assert_eq!(fields.string()?, Some("seven!!"));
+		assert_eq!(fields.string()?, Some("eight!!!"));
+		assert_eq!(fields.string()?, None);
+		assert_eq!(fields.long()?, i64::MIN);
+		assert_eq!(fields.id()?, 3);
+		assert_eq!(fields.properties()?, properties());
+		assert_eq!(fields.int()?, 42);
+		Ok(())
+	}
+
+	#[test]
+	fn round_trip() {
+		let data = message();
+		assert_eq!(data.len() % ALIGNMENT, 0);
+		parse_message(&data).unwrap();
+	}
+
+	#[test]
+	fn truncated() {
+		let data = message();
+		for len in 0..data.len() {
+			assert!(parse_message(&data[..len]).is_err(), "{len} bytes");
+		}
+	}
+
+	#[test]
+	fn wrong_kind() {
+		let mut builder = Builder::default();
+		builder.structure(|fields| {
+			fields.int(1).int(2);
+		});
+		let data = builder.into_bytes();
+		let mut fields = Parser::message(&data).unwrap();
+		assert!(fields.string().is_err());
+		assert!(fields.long().is_err());
+	}
+}
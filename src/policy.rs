@@ -0,0 +1,659 @@
+//! The decision of which streams count as activity.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::model::{Properties, Sink, SinkInput, Source, SourceOutput, State};
+
+/// The stream properties that rules may match on.
+pub const RULE_KEYS: [&str; 4] = [
+	"application.name",
+	"application.process.binary",
+	"application.id",
+	"media.name",
+];
+
+/// The keys that device rules may match on: the device's name and description, and some of its properties.
+pub const DEVICE_RULE_KEYS: [&str; 5] = [
+	"name",
+	"description",
+	"device.bus",
+	"device.form_factor",
+	"device.api",
+];
+
+/// The `media.role`s that count as activity unless configured otherwise: everything Pulseaudio defines except `event` (notification sounds and the like), `a11y` (screen readers), and `test`.
+pub const DEFAULT_ROLES: [&str; 6] = ["video", "music", "game", "phone", "animation", "production"];
+
+/// What counts as activity.
+#[derive(Debug)]
+pub struct Policy {
+	pub playback: Direction,
+	pub capture: Direction,
+	/// The `media.role`s that count. Streams without a role always count.
+	pub roles: BTreeSet<String>,
+	/// If set, playback streams that are playing only silence don't count. This is up to the backend, which may not support it.
+	pub silence_detection: Option<SilenceDetection>,
+	/// If set, streams that are muted or at zero volume don't count.
+	pub ignore_muted_streams: bool,
+	/// If set, streams whose sink or source is muted or at zero volume don't count.
+	pub ignore_muted_devices: bool,
+	/// If set, capture streams that record from monitor sources or only measure levels count like any other.
+	pub count_monitors: bool,
+}
+
+impl Default for Policy {
+	fn default() -> Self {
+		Self {
+			playback: Direction::default(),
+			capture: Direction::default(),
+			roles: DEFAULT_ROLES.into_iter().map(str::to_owned).collect(),
+			silence_detection: None,
+			ignore_muted_streams: false,
+			ignore_muted_devices: false,
+			count_monitors: false,
+		}
+	}
+}
+
+impl Policy {
+	/// Whether any stream that is playing or recording counts.
+	pub fn is_active(&self, state: &State) -> bool {
+		self.is_playback_active(state) || self.is_capture_active(state)
+	}
+
+	/// Whether any stream that is playing counts.
+	pub fn is_playback_active(&self, state: &State) -> bool {
+		state
+			.sink_inputs
+			.values()
+			.any(|stream| self.playback_counts(state, stream))
+	}
+
+	/// Whether any stream that is recording counts.
+	pub fn is_capture_active(&self, state: &State) -> bool {
+		state
+			.source_outputs
+			.values()
+			.any(|stream| self.capture_counts(state, stream))
+	}
+
+	/// Whether a playback stream counts as activity.
+	pub fn playback_counts(&self, state: &State, stream: &SinkInput) -> bool {
+		self.playback_verdict(state, stream).counts()
+	}
+
+	/// Whether a capture stream counts as activity.
+	pub fn capture_counts(&self, state: &State, stream: &SourceOutput) -> bool {
+		self.capture_verdict(state, stream).counts()
+	}
+
+	/// Whether a playback stream counts as activity, and why.
+	pub fn playback_verdict<'a>(&'a self, state: &'a State, stream: &'a SinkInput) -> Verdict<'a> {
+		if !self.playback.enabled {
+			return Verdict::Disabled;
+		}
+		if stream.corked {
+			return Verdict::Corked;
+		}
+		if stream.audible == Some(false) {
+			return Verdict::Silent;
+		}
+		if self.ignore_muted_streams && stream.is_muted() {
+			return Verdict::Muted;
+		}
+		let sink = stream.sink.and_then(|sink| state.sinks.get(&sink));
+		if self.ignore_muted_devices && sink.is_some_and(Sink::is_muted) {
+			return Verdict::DeviceMuted;
+		}
+		if !self.playback.devices.is_empty() {
+			let properties = sink
+				.map(|sink| device_properties(&sink.name, sink.description.as_deref(), &sink.properties))
+				.unwrap_or_default();
+			if let Err(deny) = self.playback.devices.check(&properties) {
+				return Verdict::DeviceExcluded(deny);
+			}
+		}
+		self.check(&self.playback.rules, &stream.properties)
+	}
+
+	/// Whether a capture stream counts as activity, and why.
+	pub fn capture_verdict<'a>(&'a self, state: &'a State, stream: &'a SourceOutput) -> Verdict<'a> {
+		if !self.capture.enabled {
+			return Verdict::Disabled;
+		}
+		if stream.corked {
+			return Verdict::Corked;
+		}
+		if !self.count_monitors && state.is_monitoring(stream) {
+			return Verdict::Monitoring;
+		}
+		if self.ignore_muted_streams && stream.is_muted() {
+			return Verdict::Muted;
+		}
+		let source = stream.source.and_then(|source| state.sources.get(&source));
+		if self.ignore_muted_devices && source.is_some_and(Source::is_muted) {
+			return Verdict::DeviceMuted;
+		}
+		if !self.capture.devices.is_empty() {
+			let properties = source
+				.map(|source| {
+					device_properties(
+						&source.name,
+						source.description.as_deref(),
+						&source.properties,
+					)
+				})
+				.unwrap_or_default();
+			if let Err(deny) = self.capture.devices.check(&properties) {
+				return Verdict::DeviceExcluded(deny);
+			}
+		}
+		self.check(&self.capture.rules, &stream.properties)
+	}
+
+	/// Checks a stream's role and then its properties against `rules`.
+	fn check<'a>(&'a self, rules: &'a Rules, properties: &'a Properties) -> Verdict<'a> {
+		if let Some(role) = properties.get("media.role") {
+			if !self.roles.contains(role) {
+				return Verdict::Role(role);
+			}
+		}
+		match rules.check(properties) {
+			Ok(allow) => Verdict::Counts(allow),
+			Err(deny) => Verdict::Excluded(deny),
+		}
+	}
+}
+
+/// Why a stream counts as activity or doesn't, as decided by [`Policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict<'a> {
+	/// The stream counts. If there are allow rules, this is the one it matched.
+	Counts(Option<&'a Matcher>),
+	/// Streams in its direction don't count at all.
+	Disabled,
+	/// The stream is paused.
+	Corked,
+	/// The stream is playing silence.
+	Silent,
+	/// The stream records what is being played, or only measures levels.
+	Monitoring,
+	Muted,
+	/// The stream's sink or source is muted.
+	DeviceMuted,
+	/// The stream's device matched this deny rule, or none of the allow rules.
+	DeviceExcluded(Option<&'a Matcher>),
+	/// The stream's `media.role` doesn't count.
+	Role(&'a str),
+	/// The stream matched this deny rule, or none of the allow rules.
+	Excluded(Option<&'a Matcher>),
+}
+
+impl Verdict<'_> {
+	pub fn counts(self) -> bool {
+		matches!(self, Self::Counts(_))
+	}
+}
+
+impl Display for Verdict<'_> {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Counts(None) => formatter.write_str("counts"),
+			Self::Counts(Some(allow)) => write!(formatter, "counts, allowed by {allow}"),
+			Self::Disabled => formatter.write_str("doesn't count, as its direction is disabled"),
+			Self::Corked => formatter.write_str("doesn't count, as it is paused"),
+			Self::Silent => formatter.write_str("doesn't count, as it is silent"),
+			Self::Monitoring => {
+				formatter.write_str("doesn't count, as it records a monitor or only measures levels")
+			}
+			Self::Muted => formatter.write_str("doesn't count, as it is muted"),
+			Self::DeviceMuted => formatter.write_str("doesn't count, as its device is muted"),
+			Self::DeviceExcluded(None) => {
+				formatter.write_str("doesn't count, as its device matches no allow rule")
+			}
+			Self::DeviceExcluded(Some(deny)) => {
+				write!(
+					formatter,
+					"doesn't count, as its device is denied by {deny}"
+				)
+			}
+			Self::Role(role) => write!(formatter, "doesn't count, as its role is {role}"),
+			Self::Excluded(None) => formatter.write_str("doesn't count, as it matches no allow rule"),
+			Self::Excluded(Some(deny)) => write!(formatter, "doesn't count, as it is denied by {deny}"),
+		}
+	}
+}
+
+/// The policy for one direction of stream, playback or capture.
+#[derive(Debug)]
+pub struct Direction {
+	/// If not set, no stream in this direction counts.
+	pub enabled: bool,
+	pub rules: Rules,
+	/// Rules for the sinks that playback streams play to, or the sources that capture streams record from, using [`DEVICE_RULE_KEYS`].
+	///
+	/// If the backend doesn't know a stream's device, the stream matches none of them.
+	pub devices: Rules,
+}
+
+impl Default for Direction {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			rules: Rules::default(),
+			devices: Rules::default(),
+		}
+	}
+}
+
+/// The properties of a sink or source as device rules see them: its own, plus its `name` and `description`.
+fn device_properties(name: &str, description: Option<&str>, properties: &Properties) -> Properties {
+	let mut properties = properties.clone();
+	properties.insert("name".into(), name.into());
+	if let Some(description) = description {
+		properties.insert("description".into(), description.into());
+	}
+	properties
+}
+
+/// How to tell silent playback streams from audible ones, by their peak level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SilenceDetection {
+	/// The peak level, in dBFS, above which a stream is audible.
+	pub threshold: f32,
+	/// How long the level has to stay above the threshold for a stream to become audible, or below it to become silent again.
+	pub duration: Duration,
+}
+
+impl SilenceDetection {
+	/// Checks that `threshold` is a level in dBFS that a stream can rise above: finite and at most 0.
+	pub fn check_threshold(threshold: f32) -> Result<f32, String> {
+		if threshold.is_finite() && threshold <= 0.0 {
+			Ok(threshold)
+		} else {
+			Err(format!(
+				"{threshold} is not a valid threshold, which must be 0 dBFS or below"
+			))
+		}
+	}
+
+	/// The threshold as a linear amplitude, where 1 is full scale.
+	pub fn threshold_amplitude(&self) -> f32 {
+		10f32.powf(self.threshold / 20.0)
+	}
+}
+
+impl Default for SilenceDetection {
+	fn default() -> Self {
+		Self {
+			threshold: -50.0,
+			duration: Duration::from_secs(2),
+		}
+	}
+}
+
+/// Which applications may inhibit.
+#[derive(Debug, Default, Clone)]
+pub struct Rules {
+	/// If not empty, only streams matching one of these count.
+	pub allow: Vec<Matcher>,
+	/// Streams matching any of these never count, even if they are allowed.
+	pub deny: Vec<Matcher>,
+}
+
+impl Rules {
+	pub fn is_empty(&self) -> bool {
+		self.allow.is_empty() && self.deny.is_empty()
+	}
+
+	/// Whether a stream or device with these properties is permitted.
+	///
+	/// Returns the allow rule that matched, or `None` if there are no allow rules; or else the deny rule that matched, or `None` if no allow rule did.
+	pub fn check(&self, properties: &Properties) -> Result<Option<&Matcher>, Option<&Matcher>> {
+		if let Some(deny) = self.deny.iter().find(|matcher| matcher.matches(properties)) {
+			return Err(Some(deny));
+		}
+		if self.allow.is_empty() {
+			return Ok(None);
+		}
+		match self
+			.allow
+			.iter()
+			.find(|matcher| matcher.matches(properties))
+		{
+			Some(allow) => Ok(Some(allow)),
+			None => Err(None),
+		}
+	}
+}
+
+/// A pattern for one property of a stream, written `key=pattern`, such as `application.process.binary=mpv`.
+///
+/// `*` in the pattern matches any run of characters; everything else must match exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matcher {
+	key: String,
+	pattern: String,
+}
+
+impl Matcher {
+	/// Parses a matcher for a device rule, which uses [`DEVICE_RULE_KEYS`] rather than [`RULE_KEYS`].
+	pub fn parse_device(raw: &str) -> Result<Self, InvalidMatcher> {
+		Self::parse(raw, &DEVICE_RULE_KEYS)
+	}
+
+	fn parse(raw: &str, keys: &'static [&'static str]) -> Result<Self, InvalidMatcher> {
+		let (key, pattern) = raw
+			.split_once('=')
+			.ok_or_else(|| InvalidMatcher::MissingEquals(raw.to_owned()))?;
+		if !keys.contains(&key) {
+			return Err(InvalidMatcher::UnknownKey(key.to_owned(), keys));
+		}
+		Ok(Self {
+			key: key.to_owned(),
+			pattern: pattern.to_owned(),
+		})
+	}
+
+	/// Whether the stream has the property and it matches. A stream without the property never matches.
+	pub fn matches(&self, properties: &Properties) -> bool {
+		properties
+			.get(&self.key)
+			.is_some_and(|value| glob_matches(&self.pattern, value))
+	}
+}
+
+impl Display for Matcher {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		write!(formatter, "{}={}", self.key, self.pattern)
+	}
+}
+
+#[derive(Debug)]
+pub enum InvalidMatcher {
+	MissingEquals(String),
+	/// The key, and the keys that would have been accepted.
+	UnknownKey(String, &'static [&'static str]),
+}
+
+impl Display for InvalidMatcher {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::MissingEquals(raw) => write!(formatter, "expected `key=pattern` but got {raw:?}"),
+			Self::UnknownKey(key, keys) => {
+				write!(formatter, "cannot match on {key:?}, expected one of")?;
+				for key in *keys {
+					write!(formatter, " {key}")?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+impl std::error::Error for InvalidMatcher {}
+
+impl FromStr for Matcher {
+	type Err = InvalidMatcher;
+
+	fn from_str(raw: &str) -> Result<Self, Self::Err> {
+		Self::parse(raw, &RULE_KEYS)
+	}
+}
+
+/// Matches `value` against `pattern`, in which `*` matches any run of characters.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+	let mut parts = pattern.split('*');
+	// There is always a first part, even if it is empty.
+	let first = parts.next().unwrap_or_default();
+	let Some(mut rest) = value.strip_prefix(first) else {
+		return false;
+	};
+	let Some(last) = parts.next_back() else {
+		// No `*` at all.
+		return rest.is_empty();
+	};
+	for part in parts {
+		let Some(position) = rest.find(part) else {
+			return false;
+		};
+		rest = &rest[position + part.len()..];
+	}
+	rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::VOLUME_NORM;
+
+	fn properties(pairs: &[(&str, &str)]) -> Properties {
+		pairs
+			.iter()
+			.map(|&(key, value)| (key.to_owned(), value.to_owned()))
+			.collect()
+	}
+
+	fn matcher(raw: &str) -> Matcher {
+		raw.parse().unwrap()
+	}
+
+	#[test]
+	fn globs() {
+		assert!(glob_matches("mpv", "mpv"));
+		assert!(!glob_matches("mpv", "mpv2"));
+		assert!(glob_matches("firefox*", "firefox-bin"));
+		assert!(glob_matches("*zoom*", "us.zoom.Zoom"));
+		assert!(glob_matches("a*b*c", "abbc"));
+		assert!(!glob_matches("a*b*c", "acb"));
+		assert!(!glob_matches("ab*ba", "aba"));
+		assert!(glob_matches("*", ""));
+	}
+
+	#[test]
+	fn allowlist() {
+		let rules = Rules {
+			allow: vec![
+				matcher("application.process.binary=mpv"),
+				matcher("application.name=Firefox"),
+			],
+			deny: Vec::new(),
+		};
+		let permits = |properties| rules.check(&properties).is_ok();
+		assert!(permits(properties(&[(
+			"application.process.binary",
+			"mpv"
+		)])));
+		assert!(permits(properties(&[("application.name", "Firefox")])));
+		assert!(!permits(properties(&[("application.name", "Discord")])));
+		assert!(!permits(Properties::new()));
+	}
+
+	#[test]
+	fn denylist_overrides_allowlist() {
+		let rules = Rules {
+			allow: vec![matcher("application.name=*")],
+			deny: vec![matcher("media.name=*notification*")],
+		};
+		assert!(rules
+			.check(&properties(&[("application.name", "Firefox")]))
+			.is_ok());
+		assert!(rules
+			.check(&properties(&[
+				("application.name", "Firefox"),
+				("media.name", "notification sound"),
+			]))
+			.is_err());
+	}
+
+	#[test]
+	fn roles() {
+		let policy = Policy::default();
+		let counts = |properties| policy.check(&policy.playback.rules, &properties).counts();
+		assert!(counts(properties(&[("media.role", "video")])));
+		assert!(counts(Properties::new()));
+		assert!(!counts(properties(&[("media.role", "event")])));
+		assert!(!counts(properties(&[("media.role", "notification")])));
+	}
+
+	#[test]
+	fn muted() {
+		let mut state = State::default();
+		state.sinks.insert(
+			0,
+			Sink {
+				index: 0,
+				name: "null".into(),
+				description: None,
+				properties: Properties::new(),
+				mute: false,
+				volume: vec![0, 0],
+				monitor_source: None,
+			},
+		);
+		state.sink_inputs.insert(
+			1,
+			SinkInput {
+				index: 1,
+				client: None,
+				sink: Some(0),
+				properties: Properties::new(),
+				corked: false,
+				mute: false,
+				volume: vec![VOLUME_NORM, VOLUME_NORM],
+				audible: None,
+			},
+		);
+
+		let mut policy = Policy::default();
+		assert!(policy.is_active(&state));
+		policy.ignore_muted_streams = true;
+		assert!(policy.is_active(&state));
+		policy.ignore_muted_devices = true;
+		assert!(!policy.is_active(&state));
+	}
+
+	#[test]
+	fn directions() {
+		let mut state = State::default();
+		state.source_outputs.insert(
+			0,
+			SourceOutput {
+				index: 0,
+				client: None,
+				source: None,
+				properties: properties(&[("application.process.binary", "zoom")]),
+				corked: false,
+				mute: false,
+				volume: Vec::new(),
+				peak_detect: false,
+			},
+		);
+
+		let mut policy = Policy::default();
+		policy
+			.playback
+			.rules
+			.allow
+			.push(matcher("application.process.binary=mpv"));
+		assert!(policy.is_active(&state));
+		policy.capture.enabled = false;
+		assert!(!policy.is_active(&state));
+	}
+
+	#[test]
+	fn devices() {
+		let mut state = State::default();
+		state.sinks.insert(
+			0,
+			Sink {
+				index: 0,
+				name: "alsa_output.pci-0000_00_1f.3.hdmi-stereo".into(),
+				description: Some("Built-in Audio Digital Stereo (HDMI)".into()),
+				properties: properties(&[("device.bus", "pci"), ("device.form_factor", "internal")]),
+				mute: false,
+				volume: Vec::new(),
+				monitor_source: None,
+			},
+		);
+		let stream = |sink| SinkInput {
+			index: 1,
+			client: None,
+			sink,
+			properties: Properties::new(),
+			corked: false,
+			mute: false,
+			volume: Vec::new(),
+			audible: None,
+		};
+
+		let mut policy = Policy::default();
+		policy
+			.playback
+			.devices
+			.allow
+			.push(Matcher::parse_device("name=*hdmi*").unwrap());
+		state.sink_inputs.insert(1, stream(Some(0)));
+		assert!(policy.is_active(&state));
+		state.sink_inputs.insert(1, stream(None));
+		assert!(!policy.is_active(&state));
+
+		assert!(Matcher::parse_device("application.name=mpv").is_err());
+	}
+
+	#[test]
+	fn verdicts() {
+		let rules = Rules {
+			allow: vec![matcher("application.name=*")],
+			deny: vec![matcher("media.name=*notification*")],
+		};
+		let firefox = properties(&[("application.name", "Firefox")]);
+		assert_eq!(rules.check(&firefox), Ok(Some(&rules.allow[0])));
+		let notification = properties(&[
+			("application.name", "Firefox"),
+			("media.name", "notification"),
+		]);
+		assert_eq!(rules.check(&notification), Err(Some(&rules.deny[0])));
+		assert_eq!(rules.check(&Properties::new()), Err(None));
+
+		let policy = Policy::default();
+		let event = properties(&[("media.role", "event")]);
+		assert_eq!(
+			policy.check(&policy.playback.rules, &event),
+			Verdict::Role("event")
+		);
+		assert_eq!(
+			policy.check(&policy.playback.rules, &event).to_string(),
+			"doesn't count, as its role is event"
+		);
+	}
+
+	#[test]
+	fn unknown_key() {
+		assert!(matches!(
+			"client.id=1".parse::<Matcher>(),
+			Err(InvalidMatcher::UnknownKey(key, _)) if key == "client.id"
+		));
+		assert!(matches!(
+			"mpv".parse::<Matcher>(),
+			Err(InvalidMatcher::MissingEquals(_))
+		));
+	}
+
+	#[test]
+	fn threshold_amplitude() {
+		let amplitude = |threshold| {
+			SilenceDetection {
+				threshold,
+				..SilenceDetection::default()
+			}
+			.threshold_amplitude()
+		};
+		assert!((amplitude(-50.0) - 0.003_162).abs() < 1e-6);
+		assert!((amplitude(-20.0) - 0.1).abs() < 1e-6);
+		assert!((amplitude(0.0) - 1.0).abs() < f32::EPSILON);
+	}
+}
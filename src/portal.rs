@@ -0,0 +1,213 @@
+//! Inhibiting idle through the `org.freedesktop.portal.Inhibit` desktop portal, which sandboxes such as Flatpak let through when they hide the compositor's inhibitors and filter the rest of the session bus.
+
+use std::collections::HashMap;
+
+use zbus::blocking::Connection;
+use zbus::proxy;
+use zbus::zvariant::{OwnedObjectPath, Value};
+
+use crate::inhibit::{self, Target};
+
+#[proxy(
+	interface = "org.freedesktop.portal.Inhibit",
+	default_service = "org.freedesktop.portal.Desktop",
+	default_path = "/org/freedesktop/portal/desktop",
+	gen_async = false,
+	blocking_name = "InhibitProxy"
+)]
+trait Inhibit {
+	fn inhibit(
+		&self,
+		window: &str,
+		flags: u32,
+		options: HashMap<&str, &Value<'_>>,
+	) -> zbus::Result<OwnedObjectPath>;
+}
+
+/// The request that an inhibit is released by closing.
+#[proxy(
+	interface = "org.freedesktop.portal.Request",
+	default_service = "org.freedesktop.portal.Desktop",
+	gen_async = false,
+	blocking_name = "RequestProxy"
+)]
+trait Request {
+	fn close(&self) -> zbus::Result<()>;
+}
+
+/// What to inhibit besides idle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+	/// Whether to inhibit suspending too.
+	pub suspend: bool,
+}
+
+impl Flags {
+	const SUSPEND: u32 = 4;
+	const IDLE: u32 = 8;
+
+	fn bits(self) -> u32 {
+		if self.suspend {
+			Self::IDLE | Self::SUSPEND
+		} else {
+			Self::IDLE
+		}
+	}
+}
+
+pub struct Portal {
+	connection: Connection,
+	inhibit: InhibitProxy<'static>,
+	flags: Flags,
+	/// The request to close to release the inhibit, while inhibited.
+	request: Option<OwnedObjectPath>,
+}
+
+impl Portal {
+	/// Connects to the session bus. Whether the portal is there is only known once idle is inhibited.
+	pub fn connect(flags: Flags) -> zbus::Result<Self> {
+		Self::new(Connection::session()?, flags)
+	}
+
+	pub fn new(connection: Connection, flags: Flags) -> zbus::Result<Self> {
+		Ok(Self {
+			inhibit: InhibitProxy::new(&connection)?,
+			connection,
+			flags,
+			request: None,
+		})
+	}
+
+	fn close(&self, request: OwnedObjectPath) -> zbus::Result<()> {
+		RequestProxy::builder(&self.connection)
+			.path(request)?
+			.build()?
+			.close()
+	}
+}
+
+impl Target for Portal {
+	fn set_inhibited(&mut self, inhibited: bool) {
+		if inhibited == self.request.is_some() {
+			return;
+		}
+
+		if let Some(request) = self.request.take() {
+			if let Err(error) = self.close(request) {
+				eprintln!("cannot stop inhibiting idle through the portal: {error}");
+			}
+		} else {
+			let reason = Value::from(inhibit::WHY);
+			let options = HashMap::from([("reason", &reason)]);
+			match self.inhibit.inhibit("", self.flags.bits(), options) {
+				Ok(request) => self.request = Some(request),
+				Err(error) => eprintln!("cannot inhibit idle through the portal: {error}"),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+
+	use zbus::interface;
+	use zbus::object_server::ObjectServer;
+	use zbus::zvariant::OwnedValue;
+
+	use super::*;
+	use crate::test_bus::{self, TestBus};
+
+	/// An inhibit that is in place, with the flags and reason it was asked for with.
+	type Inhibited = (OwnedObjectPath, u32, String);
+
+	/// Exports a request object for each inhibit, which the inhibit lasts until closed through.
+	#[derive(Default)]
+	struct Stub {
+		inhibited: Arc<Mutex<Vec<Inhibited>>>,
+		next: u32,
+	}
+
+	#[interface(name = "org.freedesktop.portal.Inhibit")]
+	impl Stub {
+		async fn inhibit(
+			&mut self,
+			#[zbus(object_server)] server: &ObjectServer,
+			window: &str,
+			flags: u32,
+			options: HashMap<String, OwnedValue>,
+		) -> zbus::fdo::Result<OwnedObjectPath> {
+			if !window.is_empty() {
+				return Err(zbus::fdo::Error::InvalidArgs(format!(
+					"no window {window:?}"
+				)));
+			}
+			self.next += 1;
+			let path = OwnedObjectPath::try_from(format!(
+				"/org/freedesktop/portal/desktop/request/1_0/t{}",
+				self.next
+			))
+			.unwrap();
+			let reason = options
+				.get("reason")
+				.and_then(|reason| String::try_from(reason.try_clone().ok()?).ok())
+				.unwrap_or_default();
+			self
+				.inhibited
+				.lock()
+				.unwrap()
+				.push((path.clone(), flags, reason));
+			let request = StubRequest {
+				path: path.clone(),
+				inhibited: Arc::clone(&self.inhibited),
+			};
+			server.at(&path, request).await?;
+			Ok(path)
+		}
+	}
+
+	/// Releases an inhibit when closed.
+	struct StubRequest {
+		path: OwnedObjectPath,
+		inhibited: Arc<Mutex<Vec<Inhibited>>>,
+	}
+
+	#[interface(name = "org.freedesktop.portal.Request")]
+	impl StubRequest {
+		fn close(&self) {
+			self
+				.inhibited
+				.lock()
+				.unwrap()
+				.retain(|(path, _, _)| *path != self.path);
+		}
+	}
+
+	#[test]
+	fn inhibits() {
+		let Some(bus) = TestBus::start() else {
+			return;
+		};
+		let stub = Stub::default();
+		let inhibited = Arc::clone(&stub.inhibited);
+		let _service = bus.serve(
+			"org.freedesktop.portal.Desktop",
+			"/org/freedesktop/portal/desktop",
+			stub,
+		);
+
+		let flags = |inhibited: &[Inhibited]| -> Vec<u32> {
+			inhibited.iter().map(|&(_, flags, _)| flags).collect()
+		};
+		let mut portal = Portal::new(bus.connect(), Flags::default()).unwrap();
+		test_bus::check_round_trips(&mut portal, || inhibited.lock().unwrap().len());
+		portal.set_inhibited(true);
+		assert_eq!(flags(&inhibited.lock().unwrap()), [8]);
+		assert_eq!(inhibited.lock().unwrap()[0].2, inhibit::WHY);
+		portal.set_inhibited(false);
+
+		let mut portal = Portal::new(bus.connect(), Flags { suspend: true }).unwrap();
+		portal.set_inhibited(true);
+		assert_eq!(flags(&inhibited.lock().unwrap()), [12]);
+	}
+}
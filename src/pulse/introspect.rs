@@ -0,0 +1,383 @@
+//! Parsing of the replies to introspection commands into our model.
+//!
+//! The layout of each reply depends on the negotiated protocol version; the version checks mirror libpulse's.
+
+use std::io;
+
+use super::tagstruct::Reader;
+use crate::model::{Sink, SinkInput, Source, SourceOutput, PEAK_RESAMPLE_METHOD};
+
+const INVALID_INDEX: u32 = u32::MAX;
+
+fn optional_index(index: u32) -> Option<u32> {
+	(index != INVALID_INDEX).then_some(index)
+}
+
+pub fn sink_input(reader: &mut Reader<'_>, version: u32) -> io::Result<SinkInput> {
+	let index = reader.u32()?;
+	let _name = reader.string()?;
+	let _owner_module = reader.u32()?;
+	let client = optional_index(reader.u32()?);
+	let sink = optional_index(reader.u32()?);
+	reader.sample_spec()?;
+	let _channel_map = reader.channel_map()?;
+	let volume = reader.cvolume()?;
+	let _buffer_usec = reader.usec()?;
+	let _sink_usec = reader.usec()?;
+	let _resample_method = reader.string()?;
+	let _driver = reader.string()?;
+	let mute = reader.bool()?;
+	let properties = reader.properties()?;
+	let corked = reader.bool()?;
+	let _has_volume = reader.bool()?;
+	let _volume_writable = reader.bool()?;
+	if version >= 21 {
+		let _format = reader.format_info()?;
+	}
+
+	Ok(SinkInput {
+		index,
+		client,
+		sink,
+		properties,
+		corked,
+		mute,
+		volume,
+		audible: None,
+	})
+}
+
+pub fn source_output(reader: &mut Reader<'_>, version: u32) -> io::Result<SourceOutput> {
+	let index = reader.u32()?;
+	let _name = reader.string()?;
+	let _owner_module = reader.u32()?;
+	let client = optional_index(reader.u32()?);
+	let source = optional_index(reader.u32()?);
+	reader.sample_spec()?;
+	let _channel_map = reader.channel_map()?;
+	let _buffer_usec = reader.usec()?;
+	let _source_usec = reader.usec()?;
+	let resample_method = reader.string()?;
+	let _driver = reader.string()?;
+	let properties = reader.properties()?;
+	let corked = reader.bool()?;
+	let (volume, mute) = if version >= 22 {
+		let volume = reader.cvolume()?;
+		let mute = reader.bool()?;
+		let _has_volume = reader.bool()?;
+		let _volume_writable = reader.bool()?;
+		let _format = reader.format_info()?;
+		(volume, mute)
+	} else {
+		(Vec::new(), false)
+	};
+
+	Ok(SourceOutput {
+		index,
+		client,
+		source,
+		properties,
+		corked,
+		mute,
+		volume,
+		peak_detect: resample_method.as_deref() == Some(PEAK_RESAMPLE_METHOD),
+	})
+}
+
+pub fn sink(reader: &mut Reader<'_>, version: u32) -> io::Result<Sink> {
+	let index = reader.u32()?;
+	let name = reader.string()?.unwrap_or_default();
+	let description = reader.string()?;
+	reader.sample_spec()?;
+	let _channel_map = reader.channel_map()?;
+	let _owner_module = reader.u32()?;
+	let volume = reader.cvolume()?;
+	let mute = reader.bool()?;
+	let _monitor_source = reader.u32()?;
+	let monitor_source = reader.string()?;
+	let _latency = reader.usec()?;
+	let _driver = reader.string()?;
+	let _flags = reader.u32()?;
+	let properties = reader.properties()?;
+	let _configured_latency = reader.usec()?;
+	device_tail(reader, version)?;
+	if version >= 21 {
+		formats(reader)?;
+	}
+
+	Ok(Sink {
+		index,
+		name,
+		description,
+		properties,
+		mute,
+		volume,
+		monitor_source,
+	})
+}
+
+pub fn source(reader: &mut Reader<'_>, version: u32) -> io::Result<Source> {
+	let index = reader.u32()?;
+	let name = reader.string()?.unwrap_or_default();
+	let description = reader.string()?;
+	reader.sample_spec()?;
+	let _channel_map = reader.channel_map()?;
+	let _owner_module = reader.u32()?;
+	let volume = reader.cvolume()?;
+	let mute = reader.bool()?;
+	let _monitor_of_sink = reader.u32()?;
+	let monitor_of_sink = reader.string()?;
+	let _latency = reader.usec()?;
+	let _driver = reader.string()?;
+	let _flags = reader.u32()?;
+	let properties = reader.properties()?;
+	let _configured_latency = reader.usec()?;
+	device_tail(reader, version)?;
+	if version >= 22 {
+		formats(reader)?;
+	}
+
+	Ok(Source {
+		index,
+		name,
+		description,
+		properties,
+		mute,
+		volume,
+		monitor_of_sink,
+	})
+}
+
+/// Skips the fields that sinks and sources share after their properties: base volume, state, volume steps, card, and ports.
+fn device_tail(reader: &mut Reader<'_>, version: u32) -> io::Result<()> {
+	let _base_volume = reader.volume()?;
+	let _state = reader.u32()?;
+	let _volume_steps = reader.u32()?;
+	let _card = reader.u32()?;
+
+	let ports = reader.u32()?;
+	for _ in 0..ports {
+		let _name = reader.string()?;
+		let _description = reader.string()?;
+		let _priority = reader.u32()?;
+		if version >= 24 {
+			let _available = reader.u32()?;
+		}
+	}
+	let _active_port = reader.string()?;
+
+	Ok(())
+}
+
+fn formats(reader: &mut Reader<'_>) -> io::Result<()> {
+	let count = reader.u8()?;
+	for _ in 0..count {
+		let _format = reader.format_info()?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+	use super::*;
+	use crate::model::Properties;
+	use crate::pulse::tagstruct::Writer;
+
+	const VERSIONS: [u32; 2] = [22, 32];
+
+	fn properties(name: &str) -> Properties {
+		Properties::from([("application.name".into(), name.into())])
+	}
+
+	/// A reply describing mpv playing to sink 1, as sink input `index`.
+	pub(in crate::pulse) fn sink_input_reply(version: u32, index: u32, corked: bool) -> Vec<u8> {
+		let mut writer = Writer::default();
+		writer
+			.u32(index)
+			.string(Some("playback"))
+			.u32(INVALID_INDEX)
+			.u32(88)
+			.u32(1)
+			.sample_spec(3, 2, 44_100)
+			.channel_map(&[1, 2])
+			.cvolume(&[0x10000, 0x8000])
+			.usec(1000)
+			.usec(2000)
+			.string(None)
+			.string(Some("protocol-native.c"))
+			.bool(true)
+			.properties(&properties("mpv"))
+			.bool(corked)
+			.bool(true)
+			.bool(true);
+		if version >= 21 {
+			writer.format_info(1, &Properties::new());
+		}
+		writer.into_bytes()
+	}
+
+	fn source_output_reply(version: u32) -> Vec<u8> {
+		let mut writer = Writer::default();
+		writer
+			.u32(5)
+			.string(Some("peak detect"))
+			.u32(INVALID_INDEX)
+			.u32(INVALID_INDEX)
+			.u32(2)
+			.sample_spec(5, 1, 25)
+			.channel_map(&[0])
+			.usec(0)
+			.usec(0)
+			.string(Some(PEAK_RESAMPLE_METHOD))
+			.string(Some("protocol-native.c"))
+			.properties(&properties("pavucontrol"))
+			.bool(true);
+		if version >= 22 {
+			writer
+				.cvolume(&[0x10000])
+				.bool(true)
+				.bool(true)
+				.bool(true)
+				.format_info(1, &Properties::new());
+		}
+		writer.into_bytes()
+	}
+
+	/// A sink or source reply, which only differ in the meaning of their monitor fields and when they got formats.
+	fn device_reply(version: u32, monitor: &str, formats_since: u32) -> Vec<u8> {
+		let mut writer = Writer::default();
+		writer
+			.u32(1)
+			.string(Some("alsa_output.hdmi"))
+			.string(Some("HDMI"))
+			.sample_spec(3, 2, 48_000)
+			.channel_map(&[1, 2])
+			.u32(7)
+			.cvolume(&[0, 0])
+			.bool(false)
+			.u32(2)
+			.string(Some(monitor))
+			.usec(0)
+			.string(Some("module-alsa-card.c"))
+			.u32(0)
+			.properties(&properties("ignored"))
+			.usec(0)
+			.volume(0x10000)
+			.u32(0)
+			.u32(65537)
+			.u32(0)
+			// One port, so that its version-dependent layout matters.
+			.u32(1)
+			.string(Some("hdmi-output-0"))
+			.string(Some("HDMI / DisplayPort"))
+			.u32(5900);
+		if version >= 24 {
+			writer.u32(2);
+		}
+		writer.string(Some("hdmi-output-0"));
+		if version >= formats_since {
+			writer.u8(1).format_info(1, &Properties::new());
+		}
+		writer.into_bytes()
+	}
+
+	fn sink_reply(version: u32) -> Vec<u8> {
+		device_reply(version, "alsa_output.hdmi.monitor", 21)
+	}
+
+	fn source_reply(version: u32) -> Vec<u8> {
+		device_reply(version, "alsa_output.hdmi", 22)
+	}
+
+	/// Parses all of `data`, failing if anything is left over.
+	fn parse<T>(
+		data: &[u8],
+		version: u32,
+		parse: fn(&mut Reader<'_>, u32) -> io::Result<T>,
+	) -> io::Result<T> {
+		let mut reader = Reader::new(data);
+		let parsed = parse(&mut reader, version)?;
+		assert!(reader.is_empty(), "left over: {:?}", reader.rest());
+		Ok(parsed)
+	}
+
+	#[test]
+	fn sink_inputs() {
+		for version in VERSIONS {
+			let stream = parse(&sink_input_reply(version, 3, false), version, sink_input).unwrap();
+			assert_eq!(stream.index, 3);
+			assert_eq!(stream.client, Some(88));
+			assert_eq!(stream.sink, Some(1));
+			assert_eq!(stream.volume, [0x10000, 0x8000]);
+			assert!(stream.mute);
+			assert!(!stream.corked);
+			assert_eq!(stream.properties, properties("mpv"));
+		}
+	}
+
+	#[test]
+	fn source_outputs() {
+		for version in VERSIONS {
+			let stream = parse(&source_output_reply(version), version, source_output).unwrap();
+			assert_eq!(stream.index, 5);
+			assert_eq!(stream.client, None);
+			assert_eq!(stream.source, Some(2));
+			assert_eq!(stream.volume, [0x10000]);
+			assert!(stream.mute);
+			assert!(stream.corked);
+			assert!(stream.peak_detect);
+		}
+	}
+
+	#[test]
+	fn sinks() {
+		for version in VERSIONS {
+			let device = parse(&sink_reply(version), version, sink).unwrap();
+			assert_eq!(device.index, 1);
+			assert_eq!(device.name, "alsa_output.hdmi");
+			assert_eq!(device.description.as_deref(), Some("HDMI"));
+			assert_eq!(device.volume, [0, 0]);
+			assert_eq!(
+				device.monitor_source.as_deref(),
+				Some("alsa_output.hdmi.monitor")
+			);
+		}
+	}
+
+	#[test]
+	fn sources() {
+		for version in VERSIONS {
+			let device = parse(&source_reply(version), version, source).unwrap();
+			assert_eq!(device.name, "alsa_output.hdmi");
+			assert_eq!(device.monitor_of_sink.as_deref(), Some("alsa_output.hdmi"));
+		}
+	}
+
+	/// Any of the parsers, ignoring what it parsed.
+	type Parser = fn(&mut Reader<'_>, u32) -> io::Result<()>;
+
+	#[test]
+	fn truncated() {
+		for version in VERSIONS {
+			let replies: [(Vec<u8>, Parser); 4] = [
+				(sink_input_reply(version, 3, false), |reader, version| {
+					sink_input(reader, version).map(drop)
+				}),
+				(source_output_reply(version), |reader, version| {
+					source_output(reader, version).map(drop)
+				}),
+				(sink_reply(version), |reader, version| {
+					sink(reader, version).map(drop)
+				}),
+				(source_reply(version), |reader, version| {
+					source(reader, version).map(drop)
+				}),
+			];
+			for (reply, parse) in replies {
+				for len in 0..reply.len() {
+					assert!(parse(&mut Reader::new(&reply[..len]), version).is_err());
+				}
+			}
+		}
+	}
+}
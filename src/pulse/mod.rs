@@ -0,0 +1,826 @@
+//! A client for the Pulseaudio native protocol, as spoken over the server's Unix socket.
+//!
+//! This is only as much of the protocol as we need to follow playback and capture streams: authentication, subscriptions, and introspection of streams and devices.
+//! It is also understood by pipewire-pulse.
+//!
+//! For silence detection we also record from the monitor of each playing stream's sink, with the server reducing the audio to a few peak levels a second.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use self::tagstruct::{Reader, Writer};
+use crate::backend::AudioSource;
+use crate::framed::{invalid, Connection, Framing, Message};
+use crate::model::{Facility, Indexed, Properties, State};
+use crate::policy::SilenceDetection;
+
+mod introspect;
+mod tagstruct;
+
+/// The newest protocol version whose introspection replies we know how to parse.
+const PROTOCOL_VERSION: u32 = 32;
+/// The oldest protocol version that sends everything we need (properties and corked state for both stream directions).
+const MINIMUM_PROTOCOL_VERSION: u32 = 22;
+const VERSION_MASK: u32 = 0xffff;
+
+const DESCRIPTOR_SIZE: usize = 20;
+const CONTROL_CHANNEL: u32 = u32::MAX;
+/// Matches the server's own limit on frame size.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+const FRAMING: Framing = Framing {
+	server: "Pulseaudio server",
+	header_size: DESCRIPTOR_SIZE,
+	body_size: |descriptor| u32::from_be_bytes(descriptor[..4].try_into().unwrap()) as usize,
+	max_body_size: MAX_FRAME_SIZE,
+};
+const COOKIE_SIZE: usize = 256;
+const EVENT_TAG: u32 = u32::MAX;
+const INVALID_INDEX: u32 = u32::MAX;
+
+/// `PA_SAMPLE_FLOAT32LE`, in which the server sends us peak levels.
+const SAMPLE_FLOAT32LE: u8 = 5;
+const CHANNEL_POSITION_MONO: u8 = 0;
+/// Peak levels per second, the same as pavucontrol's meters.
+const METER_RATE: u32 = 25;
+/// A single sample, so that each peak level is sent as soon as it is measured.
+const METER_FRAGMENT_SIZE: u32 = 4;
+
+mod command {
+	pub const ERROR: u32 = 0;
+	pub const REPLY: u32 = 2;
+	pub const CREATE_RECORD_STREAM: u32 = 5;
+	pub const DELETE_RECORD_STREAM: u32 = 6;
+	pub const AUTH: u32 = 8;
+	pub const SET_CLIENT_NAME: u32 = 9;
+	pub const GET_SINK_INFO: u32 = 21;
+	pub const GET_SINK_INFO_LIST: u32 = 22;
+	pub const GET_SOURCE_INFO: u32 = 23;
+	pub const GET_SOURCE_INFO_LIST: u32 = 24;
+	pub const GET_SINK_INPUT_INFO: u32 = 29;
+	pub const GET_SINK_INPUT_INFO_LIST: u32 = 30;
+	pub const GET_SOURCE_OUTPUT_INFO: u32 = 31;
+	pub const GET_SOURCE_OUTPUT_INFO_LIST: u32 = 32;
+	pub const SUBSCRIBE: u32 = 35;
+	pub const RECORD_STREAM_KILLED: u32 = 65;
+	pub const SUBSCRIBE_EVENT: u32 = 66;
+}
+
+mod subscription {
+	pub const MASK_SINK: u32 = 0x0001;
+	pub const MASK_SOURCE: u32 = 0x0002;
+	pub const MASK_SINK_INPUT: u32 = 0x0004;
+	pub const MASK_SOURCE_OUTPUT: u32 = 0x0008;
+
+	pub const FACILITY_MASK: u32 = 0x000f;
+	pub const FACILITY_SINK: u32 = 0x0000;
+	pub const FACILITY_SOURCE: u32 = 0x0001;
+	pub const FACILITY_SINK_INPUT: u32 = 0x0002;
+	pub const FACILITY_SOURCE_OUTPUT: u32 = 0x0003;
+
+	pub const TYPE_MASK: u32 = 0x0030;
+	pub const TYPE_REMOVE: u32 = 0x0020;
+}
+
+/// The error code for an object that doesn't exist, such as a stream that was removed before we could fetch it.
+const ERROR_NO_ENTITY: u32 = 5;
+
+pub struct Client {
+	connection: Connection,
+	version: u32,
+	next_tag: u32,
+	/// Set until we have listed everything once. After that, the model is kept up to date one object at a time.
+	dirty: bool,
+	/// Objects that the server has told us have been created or changed, but that we have not fetched since.
+	stale: BTreeSet<(Facility, u32)>,
+	/// Set when the server has told us about a change that has not yet been reported by [`Self::wait`].
+	notified: bool,
+	state: State,
+	silence_detection: Option<SilenceDetection>,
+	/// Peak meters by the index of the sink input they measure.
+	meters: BTreeMap<u32, Meter>,
+}
+
+/// A record stream that follows the peak level of a single sink input on the monitor of its sink, like the meters in pavucontrol.
+struct Meter {
+	/// The channel on which the server sends us the levels.
+	channel: u32,
+	/// The index of our stream, which is a source output like any other.
+	source_output: u32,
+	/// The sink that the sink input was playing to when we started measuring it. If it moves, the server kills our stream.
+	sink: u32,
+	level: Level,
+}
+
+/// Whether a stream is audible, going by the peak levels measured so far.
+#[derive(Debug, Default)]
+struct Level {
+	audible: bool,
+	/// When the level started staying on the other side of the threshold from what `audible` says.
+	crossed: Option<Instant>,
+}
+
+impl Level {
+	/// Takes note of a peak level measured at `now`, changing whether the stream is audible once the level has stayed on the other side of `threshold`, an amplitude, for `duration`.
+	///
+	/// Returns whether that changed.
+	fn measure(&mut self, level: f32, threshold: f32, duration: Duration, now: Instant) -> bool {
+		if (level >= threshold) == self.audible {
+			self.crossed = None;
+			return false;
+		}
+		let crossed = *self.crossed.get_or_insert(now);
+		if now - crossed < duration {
+			return false;
+		}
+		self.audible = !self.audible;
+		self.crossed = None;
+		true
+	}
+}
+
+impl Client {
+	pub fn connect(silence_detection: Option<SilenceDetection>) -> io::Result<Self> {
+		let path = socket_path()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no Pulseaudio socket found"))?;
+		let socket = UnixStream::connect(path)?;
+
+		let mut client = Self {
+			connection: Connection::new(socket, &FRAMING),
+			version: 0,
+			next_tag: 0,
+			dirty: true,
+			stale: BTreeSet::new(),
+			notified: false,
+			state: State::default(),
+			silence_detection,
+			meters: BTreeMap::new(),
+		};
+
+		let cookie = read_cookie();
+		let reply = client.request(command::AUTH, |request| {
+			request.u32(PROTOCOL_VERSION).arbitrary(&cookie);
+		})?;
+		let server_version = Reader::new(&reply).u32()? & VERSION_MASK;
+		if server_version < MINIMUM_PROTOCOL_VERSION {
+			return Err(io::Error::new(
+				io::ErrorKind::Unsupported,
+				format!("Pulseaudio server speaks protocol version {server_version}, which is too old"),
+			));
+		}
+		client.version = server_version.min(PROTOCOL_VERSION);
+
+		let properties = Properties::from([
+			("application.name".into(), env!("CARGO_PKG_NAME").into()),
+			(
+				"application.process.id".into(),
+				std::process::id().to_string(),
+			),
+		]);
+		client.request(command::SET_CLIENT_NAME, |request| {
+			request.properties(&properties);
+		})?;
+
+		client.request(command::SUBSCRIBE, |request| {
+			request.u32(
+				subscription::MASK_SINK
+					| subscription::MASK_SOURCE
+					| subscription::MASK_SINK_INPUT
+					| subscription::MASK_SOURCE_OUTPUT,
+			);
+		})?;
+
+		Ok(client)
+	}
+
+	fn refresh(&mut self) -> io::Result<()> {
+		self.stale.clear();
+		self.state = State {
+			sink_inputs: self.list(command::GET_SINK_INPUT_INFO_LIST, introspect::sink_input)?,
+			source_outputs: self.list(
+				command::GET_SOURCE_OUTPUT_INFO_LIST,
+				introspect::source_output,
+			)?,
+			sinks: self.list(command::GET_SINK_INFO_LIST, introspect::sink)?,
+			sources: self.list(command::GET_SOURCE_INFO_LIST, introspect::source)?,
+		};
+		Ok(())
+	}
+
+	/// Fetches a single object that the server told us about, or forgets it if it is already gone.
+	fn fetch(&mut self, facility: Facility, index: u32) -> io::Result<()> {
+		match facility {
+			Facility::SinkInput => {
+				let item = self.get(
+					command::GET_SINK_INPUT_INFO,
+					index,
+					false,
+					introspect::sink_input,
+				)?;
+				replace(&mut self.state.sink_inputs, index, item);
+			}
+			Facility::SourceOutput => {
+				let item = self.get(
+					command::GET_SOURCE_OUTPUT_INFO,
+					index,
+					false,
+					introspect::source_output,
+				)?;
+				replace(&mut self.state.source_outputs, index, item);
+			}
+			Facility::Sink => {
+				let item = self.get(command::GET_SINK_INFO, index, true, introspect::sink)?;
+				replace(&mut self.state.sinks, index, item);
+			}
+			Facility::Source => {
+				let item = self.get(command::GET_SOURCE_INFO, index, true, introspect::source)?;
+				replace(&mut self.state.sources, index, item);
+			}
+		}
+		Ok(())
+	}
+
+	/// Requests a single object by index, returning `None` if it doesn't exist.
+	///
+	/// Devices can also be requested by name, so their requests have an extra (null) name field.
+	fn get<T>(
+		&mut self,
+		command: u32,
+		index: u32,
+		is_device: bool,
+		parse: fn(&mut Reader<'_>, u32) -> io::Result<T>,
+	) -> io::Result<Option<T>> {
+		let reply = match self.request(command, |request| {
+			request.u32(index);
+			if is_device {
+				request.string(None);
+			}
+		}) {
+			Ok(reply) => reply,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(error) => return Err(error),
+		};
+		parse(&mut Reader::new(&reply), self.version).map(Some)
+	}
+
+	/// Requests a list of objects and parses each entry of the reply, keying them by index.
+	fn list<T>(
+		&mut self,
+		command: u32,
+		parse: fn(&mut Reader<'_>, u32) -> io::Result<T>,
+	) -> io::Result<BTreeMap<u32, T>>
+	where
+		T: Indexed,
+	{
+		let reply = self.request(command, |_| {})?;
+		let mut reader = Reader::new(&reply);
+		let mut items = BTreeMap::new();
+		while !reader.is_empty() {
+			let item = parse(&mut reader, self.version)?;
+			items.insert(item.index(), item);
+		}
+		Ok(items)
+	}
+
+	/// Starts measuring the sink inputs that are playing and stops measuring the ones that aren't.
+	fn update_meters(&mut self) -> io::Result<()> {
+		let wanted: Vec<(u32, u32, String)> = self
+			.state
+			.sink_inputs
+			.values()
+			.filter(|stream| !stream.corked)
+			.filter_map(|stream| {
+				let sink = stream.sink?;
+				let monitor = self.state.sinks.get(&sink)?.monitor_source.clone()?;
+				Some((stream.index, sink, monitor))
+			})
+			.collect();
+
+		let unwanted: Vec<u32> = self
+			.meters
+			.iter()
+			.filter(|&(&index, meter)| {
+				!wanted
+					.iter()
+					.any(|&(wanted, sink, _)| wanted == index && sink == meter.sink)
+			})
+			.map(|(&index, _)| index)
+			.collect();
+		for index in unwanted {
+			if let Some(meter) = self.meters.remove(&index) {
+				self.delete_meter(meter.channel)?;
+			}
+		}
+
+		for (index, sink, monitor) in wanted {
+			if self.meters.contains_key(&index) {
+				continue;
+			}
+			if let Some(meter) = self.create_meter(index, sink, &monitor)? {
+				self.meters.insert(index, meter);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Creates a peak-detecting record stream on `monitor` for just the sink input `index`, returning `None` if the sink input is already gone.
+	fn create_meter(&mut self, index: u32, sink: u32, monitor: &str) -> io::Result<Option<Meter>> {
+		let properties = Properties::from([("media.name".into(), "Peak meter".into())]);
+		let reply = self.request(command::CREATE_RECORD_STREAM, |request| {
+			request
+				.sample_spec(SAMPLE_FLOAT32LE, 1, METER_RATE)
+				.channel_map(&[CHANNEL_POSITION_MONO])
+				.u32(INVALID_INDEX)
+				.string(Some(monitor))
+				// Maximum length: the server's default.
+				.u32(u32::MAX)
+				// Not corked, so that measuring starts right away.
+				.bool(false)
+				.u32(METER_FRAGMENT_SIZE)
+				// Don't remap or remix channels; fix the format, rate, or channels to the source's.
+				.bool(false)
+				.bool(false)
+				.bool(false)
+				.bool(false)
+				.bool(false)
+				// Don't move; variable rate.
+				.bool(true)
+				.bool(false)
+				// Peak detection; adjust latency.
+				.bool(true)
+				.bool(true)
+				.properties(&properties)
+				// Direct on input: record only this sink input.
+				.u32(index)
+				// Early requests.
+				.bool(false)
+				// Don't keep the sink from suspending; fail if it suspends.
+				.bool(true)
+				.bool(false)
+				// No formats besides the sample specification.
+				.u8(0)
+				// No volume, not muted, and no volume or mute set; not relative; not passthrough.
+				.cvolume(&[])
+				.bool(false)
+				.bool(false)
+				.bool(false)
+				.bool(false)
+				.bool(false);
+		});
+		let reply = match reply {
+			Ok(reply) => reply,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(error) => return Err(error),
+		};
+
+		let mut reader = Reader::new(&reply);
+		let channel = reader.u32()?;
+		let source_output = reader.u32()?;
+		Ok(Some(Meter {
+			channel,
+			source_output,
+			sink,
+			level: Level::default(),
+		}))
+	}
+
+	fn delete_meter(&mut self, channel: u32) -> io::Result<()> {
+		match self.request(command::DELETE_RECORD_STREAM, |request| {
+			request.u32(channel);
+		}) {
+			Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+			_ => Ok(()),
+		}
+	}
+
+	/// Follows the levels that a meter measured at `now`, noting when they stay on the other side of the threshold for long enough.
+	fn handle_levels(&mut self, channel: u32, data: &[u8], now: Instant) {
+		let Some(silence_detection) = self.silence_detection else {
+			return;
+		};
+		let Some(meter) = self
+			.meters
+			.values_mut()
+			.find(|meter| meter.channel == channel)
+		else {
+			return;
+		};
+
+		let threshold = silence_detection.threshold_amplitude();
+		for sample in data.chunks_exact(4) {
+			let level = f32::from_le_bytes(sample.try_into().unwrap());
+			if meter
+				.level
+				.measure(level, threshold, silence_detection.duration, now)
+			{
+				self.notified = true;
+			}
+		}
+	}
+
+	/// Sends a command and blocks until the server replies to it, returning the body of the reply.
+	///
+	/// Subscription events that arrive in the meantime are handled as usual.
+	fn request(&mut self, command: u32, body: impl FnOnce(&mut Writer)) -> io::Result<Vec<u8>> {
+		let tag = self.next_tag;
+		self.next_tag = self.next_tag.wrapping_add(1) % EVENT_TAG;
+
+		let mut request = Writer::default();
+		request.u32(command).u32(tag);
+		body(&mut request);
+		self.write_frame(&request.into_bytes())?;
+
+		loop {
+			let message = self.connection.read()?;
+			let Some(frame) = self.control_frame(message) else {
+				continue;
+			};
+			let mut reader = Reader::new(&frame);
+			let reply_command = reader.u32()?;
+			let reply_tag = reader.u32()?;
+			if reply_tag != tag {
+				self.handle_unsolicited(&frame)?;
+				continue;
+			}
+
+			match reply_command {
+				command::REPLY => {
+					return Ok(reader.rest().to_vec());
+				}
+				command::ERROR => {
+					let code = reader.u32()?;
+					let kind = if code == ERROR_NO_ENTITY {
+						io::ErrorKind::NotFound
+					} else {
+						io::ErrorKind::Other
+					};
+					return Err(io::Error::new(
+						kind,
+						format!("Pulseaudio server rejected command {command} with error {code}"),
+					));
+				}
+				other => return Err(invalid(format!("unexpected reply command {other}"))),
+			}
+		}
+	}
+
+	fn handle_unsolicited(&mut self, frame: &[u8]) -> io::Result<()> {
+		let mut reader = Reader::new(frame);
+		let command = reader.u32()?;
+		let _tag = reader.u32()?;
+		if command == command::RECORD_STREAM_KILLED {
+			let channel = reader.u32()?;
+			self.meters.retain(|_, meter| meter.channel != channel);
+			self.notified = true;
+			return Ok(());
+		}
+		if command != command::SUBSCRIBE_EVENT {
+			return Ok(());
+		}
+		let event = reader.u32()?;
+		let index = reader.u32()?;
+
+		let facility = match event & subscription::FACILITY_MASK {
+			subscription::FACILITY_SINK_INPUT => Facility::SinkInput,
+			subscription::FACILITY_SOURCE_OUTPUT => Facility::SourceOutput,
+			subscription::FACILITY_SINK => Facility::Sink,
+			subscription::FACILITY_SOURCE => Facility::Source,
+			_ => return Ok(()),
+		};
+		if event & subscription::TYPE_MASK == subscription::TYPE_REMOVE {
+			self.state.remove(facility, index);
+			self.stale.remove(&(facility, index));
+		} else {
+			self.stale.insert((facility, index));
+		}
+		self.notified = true;
+
+		Ok(())
+	}
+
+	fn write_frame(&mut self, payload: &[u8]) -> io::Result<()> {
+		let mut frame = Vec::with_capacity(DESCRIPTOR_SIZE + payload.len());
+		let length: u32 = payload
+			.len()
+			.try_into()
+			.map_err(|_| invalid("frame too large"))?;
+		for field in [length, CONTROL_CHANNEL, 0, 0, 0] {
+			frame.extend_from_slice(&field.to_be_bytes());
+		}
+		frame.extend_from_slice(payload);
+		self.connection.write(&frame)
+	}
+
+	/// Returns a frame if it is a control frame.
+	///
+	/// Audio data can only be the levels from our meters, which are handled here; `None` is returned so that the caller can check whether they changed anything.
+	fn control_frame(&mut self, Message { header, body }: Message) -> Option<Vec<u8>> {
+		let channel = u32::from_be_bytes(header[4..8].try_into().unwrap());
+		if channel == CONTROL_CHANNEL {
+			return Some(body);
+		}
+		self.handle_levels(channel, &body, Instant::now());
+		None
+	}
+}
+
+impl AudioSource for Client {
+	/// Waits until the server notifies us about a change to a stream or device, or until `timeout` elapses.
+	///
+	/// Returns whether a change occurred.
+	fn wait(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			if std::mem::take(&mut self.notified) {
+				return Ok(true);
+			}
+
+			let Some(message) = self.connection.read_until(deadline)? else {
+				return Ok(false);
+			};
+			if let Some(frame) = self.control_frame(message) {
+				self.handle_unsolicited(&frame)?;
+			}
+		}
+	}
+
+	/// Fetches whatever changed since the last call, and updates the meters if silence detection is on.
+	fn state(&mut self) -> io::Result<&State> {
+		if std::mem::take(&mut self.dirty) {
+			self.refresh()?;
+		}
+		// Fetching may handle events that make more objects stale, so take them one at a time.
+		while let Some((facility, index)) = self.stale.pop_first() {
+			self.fetch(facility, index)?;
+		}
+
+		if self.silence_detection.is_some() {
+			self.update_meters()?;
+			for (index, stream) in &mut self.state.sink_inputs {
+				stream.audible = self.meters.get(index).map(|meter| meter.level.audible);
+			}
+			// Our meters are not activity.
+			let meters = &self.meters;
+			self
+				.state
+				.source_outputs
+				.retain(|index, _| !meters.values().any(|meter| meter.source_output == *index));
+		}
+
+		Ok(&self.state)
+	}
+}
+
+fn replace<T>(items: &mut BTreeMap<u32, T>, index: u32, item: Option<T>) {
+	match item {
+		Some(item) => _ = items.insert(index, item),
+		None => _ = items.remove(&index),
+	}
+}
+
+/// Finds the server socket the same way libpulse does for local servers: `$PULSE_SERVER` if it names a Unix socket, otherwise the `native` socket in the runtime directory.
+fn socket_path() -> Option<PathBuf> {
+	if let Some(server) = std::env::var_os("PULSE_SERVER") {
+		let server = server.to_str()?;
+		let path = server.strip_prefix("unix:").unwrap_or(server);
+		return path.starts_with('/').then(|| path.into());
+	}
+
+	let runtime_dir = std::env::var_os("PULSE_RUNTIME_PATH")
+		.map(PathBuf::from)
+		.or_else(|| Some(PathBuf::from(std::env::var_os("XDG_RUNTIME_DIR")?).join("pulse")))?;
+	Some(runtime_dir.join("native"))
+}
+
+/// Reads the authentication cookie from the usual locations.
+///
+/// If there is none we send zeros; local servers typically authenticate us by our credentials on the socket anyway.
+fn read_cookie() -> Vec<u8> {
+	let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+		.map(PathBuf::from)
+		.or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")));
+	let home = std::env::var_os("HOME").map(PathBuf::from);
+
+	let candidates = [
+		std::env::var_os("PULSE_COOKIE").map(PathBuf::from),
+		config_dir.map(|dir| dir.join("pulse/cookie")),
+		home.map(|home| home.join(".pulse-cookie")),
+	];
+
+	candidates
+		.into_iter()
+		.flatten()
+		.filter_map(|path| std::fs::read(path).ok())
+		.find(|cookie| cookie.len() == COOKIE_SIZE)
+		.unwrap_or_else(|| vec![0; COOKIE_SIZE])
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Read, Write};
+	use std::thread::JoinHandle;
+
+	use super::introspect::tests::sink_input_reply;
+	use super::*;
+
+	/// A client whose server is the returned end of a socket pair, for feeding messages to by hand.
+	fn client_and_server(silence_detection: Option<SilenceDetection>) -> (Client, UnixStream) {
+		let (socket, server) = UnixStream::pair().unwrap();
+		let client = Client {
+			connection: Connection::new(socket, &FRAMING),
+			version: PROTOCOL_VERSION,
+			next_tag: 0,
+			dirty: false,
+			stale: BTreeSet::new(),
+			notified: false,
+			state: State::default(),
+			silence_detection,
+			meters: BTreeMap::new(),
+		};
+		(client, server)
+	}
+
+	/// A client that isn't connected to anything.
+	fn client(silence_detection: Option<SilenceDetection>) -> Client {
+		client_and_server(silence_detection).0
+	}
+
+	fn frame(payload: Writer) -> Vec<u8> {
+		let payload = payload.into_bytes();
+		let mut frame = Vec::new();
+		for field in [payload.len().try_into().unwrap(), CONTROL_CHANNEL, 0, 0, 0] {
+			frame.extend_from_slice(&u32::to_be_bytes(field));
+		}
+		frame.extend(payload);
+		frame
+	}
+
+	fn event(socket: &mut UnixStream, facility: u32, kind: u32, index: u32) {
+		let mut event = Writer::default();
+		event
+			.u32(command::SUBSCRIBE_EVENT)
+			.u32(EVENT_TAG)
+			.u32(facility | kind)
+			.u32(index);
+		socket.write_all(&frame(event)).unwrap();
+	}
+
+	use subscription::FACILITY_SINK_INPUT as SINK_INPUT;
+
+	const TYPE_NEW: u32 = 0x0000;
+	const TYPE_CHANGE: u32 = 0x0010;
+
+	/// Plays the server, answering every request for a sink input with `answer`, which is given the index and the socket to send events on first, and returns either a reply or an error code.
+	///
+	/// Returns the indices asked for once the client hangs up.
+	fn serve(
+		mut socket: UnixStream,
+		mut answer: impl FnMut(u32, &mut UnixStream) -> Result<Vec<u8>, u32> + Send + 'static,
+	) -> JoinHandle<Vec<u32>> {
+		std::thread::spawn(move || {
+			let mut requested = Vec::new();
+			loop {
+				let mut descriptor = [0; DESCRIPTOR_SIZE];
+				if socket.read_exact(&mut descriptor).is_err() {
+					return requested;
+				}
+				let length = u32::from_be_bytes(descriptor[..4].try_into().unwrap());
+				let mut request = vec![0; length as usize];
+				socket.read_exact(&mut request).unwrap();
+
+				let mut request = Reader::new(&request);
+				assert_eq!(request.u32().unwrap(), command::GET_SINK_INPUT_INFO);
+				let tag = request.u32().unwrap();
+				let index = request.u32().unwrap();
+				requested.push(index);
+
+				let mut reply = Writer::default();
+				match answer(index, &mut socket) {
+					Ok(body) => _ = reply.u32(command::REPLY).u32(tag).raw(&body),
+					Err(code) => _ = reply.u32(command::ERROR).u32(tag).u32(code),
+				}
+				socket.write_all(&frame(reply)).unwrap();
+			}
+		})
+	}
+
+	/// Handles events until there have been none for a moment, and then lists the sink inputs and whether they are corked.
+	fn corked(client: &mut Client) -> Vec<(u32, bool)> {
+		while client.wait(Some(Duration::from_millis(100))).unwrap() {}
+		let state = client.state().unwrap();
+		state
+			.sink_inputs
+			.values()
+			.map(|stream| (stream.index, stream.corked))
+			.collect()
+	}
+
+	#[test]
+	fn incremental() {
+		let (mut client, mut server) = client_and_server(None);
+		// Each sink input is corked the first time it is fetched, uncorked the second, and gone after that.
+		let mut fetched = BTreeMap::<u32, u32>::new();
+		let requests = serve(server.try_clone().unwrap(), move |index, _| {
+			let fetched = fetched.entry(index).or_default();
+			*fetched += 1;
+			match fetched {
+				1 | 2 => Ok(sink_input_reply(PROTOCOL_VERSION, index, *fetched == 1)),
+				_ => Err(ERROR_NO_ENTITY),
+			}
+		});
+
+		event(&mut server, SINK_INPUT, TYPE_NEW, 3);
+		event(&mut server, SINK_INPUT, TYPE_NEW, 4);
+		assert_eq!(corked(&mut client), [(3, true), (4, true)]);
+
+		event(&mut server, SINK_INPUT, TYPE_CHANGE, 4);
+		assert_eq!(corked(&mut client), [(3, true), (4, false)]);
+
+		// Removed streams are forgotten without asking, and streams that are gone by the time we ask are too.
+		event(&mut server, SINK_INPUT, subscription::TYPE_REMOVE, 3);
+		assert_eq!(corked(&mut client), [(4, false)]);
+		event(&mut server, SINK_INPUT, TYPE_CHANGE, 4);
+		assert_eq!(corked(&mut client), []);
+
+		drop((client, server));
+		assert_eq!(requests.join().unwrap(), [3, 4, 4, 4]);
+	}
+
+	#[test]
+	fn refetch_stale() {
+		let (mut client, mut server) = client_and_server(None);
+		// The first time sink input 3 is asked for, it changes again before we get the reply.
+		let mut changed = false;
+		let requests = serve(server.try_clone().unwrap(), move |index, socket| {
+			let corked = !changed;
+			if !changed {
+				event(socket, SINK_INPUT, TYPE_CHANGE, index);
+				changed = true;
+			}
+			Ok(sink_input_reply(PROTOCOL_VERSION, index, corked))
+		});
+
+		event(&mut server, SINK_INPUT, TYPE_NEW, 3);
+		assert_eq!(corked(&mut client), [(3, false)]);
+
+		drop((client, server));
+		assert_eq!(requests.join().unwrap(), [3, 3]);
+	}
+
+	fn levels(levels: &[f32]) -> Vec<u8> {
+		levels
+			.iter()
+			.flat_map(|level| level.to_le_bytes())
+			.collect()
+	}
+
+	#[test]
+	fn level() {
+		let duration = Duration::from_secs(2);
+		let start = Instant::now();
+		let mut level = Level::default();
+
+		assert!(!level.measure(0.5, 0.1, duration, start));
+		assert!(!level.measure(0.5, 0.1, duration, start + Duration::from_secs(1)));
+		// Dipping below the threshold starts over.
+		assert!(!level.measure(0.05, 0.1, duration, start + Duration::from_millis(1500)));
+		assert!(!level.measure(0.5, 0.1, duration, start + Duration::from_secs(2)));
+		assert!(!level.audible);
+		assert!(level.measure(0.5, 0.1, duration, start + Duration::from_secs(4)));
+		assert!(level.audible);
+
+		assert!(!level.measure(0.0, 0.1, duration, start + Duration::from_secs(5)));
+		assert!(level.measure(0.0, 0.1, duration, start + Duration::from_secs(7)));
+		assert!(!level.audible);
+	}
+
+	#[test]
+	fn handle_levels() {
+		let silence_detection = SilenceDetection::default();
+		let mut client = client(Some(silence_detection));
+		client.meters.insert(
+			3,
+			Meter {
+				channel: 7,
+				source_output: 10,
+				sink: 0,
+				level: Level::default(),
+			},
+		);
+
+		let start = Instant::now();
+		client.handle_levels(7, &levels(&[0.5, 0.5]), start);
+		client.handle_levels(7, &levels(&[0.5]), start + silence_detection.duration / 2);
+		// Levels for another channel are ignored.
+		client.handle_levels(8, &levels(&[0.5]), start + silence_detection.duration);
+		assert!(!client.meters[&3].level.audible);
+		assert!(!client.notified);
+
+		client.handle_levels(7, &levels(&[0.5]), start + silence_detection.duration);
+		assert!(client.meters[&3].level.audible);
+		assert!(client.notified);
+	}
+}
@@ -0,0 +1,387 @@
+//! The tagged serialization format ("tagstruct") used for all control messages of the Pulseaudio native protocol.
+
+use std::io;
+
+use crate::framed::invalid;
+use crate::model::Properties;
+
+mod tag {
+	pub const STRING: u8 = b't';
+	pub const STRING_NULL: u8 = b'N';
+	pub const U32: u8 = b'L';
+	pub const U8: u8 = b'B';
+	pub const SAMPLE_SPEC: u8 = b'a';
+	pub const ARBITRARY: u8 = b'x';
+	pub const BOOLEAN_TRUE: u8 = b'1';
+	pub const BOOLEAN_FALSE: u8 = b'0';
+	pub const USEC: u8 = b'U';
+	pub const CHANNEL_MAP: u8 = b'm';
+	pub const CVOLUME: u8 = b'v';
+	pub const PROPLIST: u8 = b'P';
+	pub const VOLUME: u8 = b'V';
+	pub const FORMAT_INFO: u8 = b'f';
+}
+
+#[derive(Default)]
+pub struct Writer {
+	data: Vec<u8>,
+}
+
+impl Writer {
+	pub fn into_bytes(self) -> Vec<u8> {
+		self.data
+	}
+
+	pub fn u32(&mut self, value: u32) -> &mut Self {
+		self.data.push(tag::U32);
+		self.data.extend_from_slice(&value.to_be_bytes());
+		self
+	}
+
+	pub fn string(&mut self, value: Option<&str>) -> &mut Self {
+		if let Some(value) = value {
+			self.data.push(tag::STRING);
+			self.data.extend_from_slice(value.as_bytes());
+			self.data.push(0);
+		} else {
+			self.data.push(tag::STRING_NULL);
+		}
+		self
+	}
+
+	pub fn u8(&mut self, value: u8) -> &mut Self {
+		self.data.push(tag::U8);
+		self.data.push(value);
+		self
+	}
+
+	pub fn bool(&mut self, value: bool) -> &mut Self {
+		self.data.push(if value {
+			tag::BOOLEAN_TRUE
+		} else {
+			tag::BOOLEAN_FALSE
+		});
+		self
+	}
+
+	pub fn sample_spec(&mut self, format: u8, channels: u8, rate: u32) -> &mut Self {
+		self.data.push(tag::SAMPLE_SPEC);
+		self.data.extend_from_slice(&[format, channels]);
+		self.data.extend_from_slice(&rate.to_be_bytes());
+		self
+	}
+
+	pub fn channel_map(&mut self, positions: &[u8]) -> &mut Self {
+		self.data.push(tag::CHANNEL_MAP);
+		self.data.push(len_u8(positions.len()));
+		self.data.extend_from_slice(positions);
+		self
+	}
+
+	pub fn cvolume(&mut self, volume: &[u32]) -> &mut Self {
+		self.data.push(tag::CVOLUME);
+		self.data.push(len_u8(volume.len()));
+		for channel in volume {
+			self.data.extend_from_slice(&channel.to_be_bytes());
+		}
+		self
+	}
+
+	pub fn arbitrary(&mut self, value: &[u8]) -> &mut Self {
+		self.data.push(tag::ARBITRARY);
+		self
+			.data
+			.extend_from_slice(&len_u32(value.len()).to_be_bytes());
+		self.data.extend_from_slice(value);
+		self
+	}
+
+	pub fn properties(&mut self, properties: &Properties) -> &mut Self {
+		self.data.push(tag::PROPLIST);
+		for (key, value) in properties {
+			let mut value = value.as_bytes().to_vec();
+			value.push(0);
+			self.string(Some(key));
+			self.u32(len_u32(value.len()));
+			self.arbitrary(&value);
+		}
+		self.string(None)
+	}
+
+	/// Appends already serialized fields.
+	#[cfg(test)]
+	pub fn raw(&mut self, fields: &[u8]) -> &mut Self {
+		self.data.extend_from_slice(fields);
+		self
+	}
+
+	#[cfg(test)]
+	pub fn usec(&mut self, value: u64) -> &mut Self {
+		self.data.push(tag::USEC);
+		self.data.extend_from_slice(&value.to_be_bytes());
+		self
+	}
+
+	#[cfg(test)]
+	pub fn volume(&mut self, value: u32) -> &mut Self {
+		self.data.push(tag::VOLUME);
+		self.data.extend_from_slice(&value.to_be_bytes());
+		self
+	}
+
+	#[cfg(test)]
+	pub fn format_info(&mut self, encoding: u8, properties: &Properties) -> &mut Self {
+		self.data.push(tag::FORMAT_INFO);
+		self.u8(encoding).properties(properties)
+	}
+}
+
+fn len_u32(len: usize) -> u32 {
+	len.try_into().expect("tagstruct field too large")
+}
+
+fn len_u8(len: usize) -> u8 {
+	len.try_into().expect("too many channels")
+}
+
+pub struct Reader<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	pub fn new(data: &'a [u8]) -> Self {
+		Self { data }
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.data.is_empty()
+	}
+
+	/// The data that has not been read yet.
+	pub fn rest(&self) -> &'a [u8] {
+		self.data
+	}
+
+	fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+		if self.data.len() < len {
+			return Err(invalid("truncated tagstruct"));
+		}
+		let (taken, rest) = self.data.split_at(len);
+		self.data = rest;
+		Ok(taken)
+	}
+
+	fn take_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+		Ok(self.take(N)?.try_into().unwrap())
+	}
+
+	fn expect_tag(&mut self, expected: u8) -> io::Result<()> {
+		let [actual] = self.take_array()?;
+		if actual == expected {
+			Ok(())
+		} else {
+			Err(invalid(format!(
+				"expected tag {:?} but got {:?}",
+				char::from(expected),
+				char::from(actual)
+			)))
+		}
+	}
+
+	fn raw_u32(&mut self) -> io::Result<u32> {
+		Ok(u32::from_be_bytes(self.take_array()?))
+	}
+
+	pub fn u32(&mut self) -> io::Result<u32> {
+		self.expect_tag(tag::U32)?;
+		self.raw_u32()
+	}
+
+	pub fn u8(&mut self) -> io::Result<u8> {
+		self.expect_tag(tag::U8)?;
+		let [value] = self.take_array()?;
+		Ok(value)
+	}
+
+	pub fn usec(&mut self) -> io::Result<u64> {
+		self.expect_tag(tag::USEC)?;
+		Ok(u64::from_be_bytes(self.take_array()?))
+	}
+
+	pub fn bool(&mut self) -> io::Result<bool> {
+		let [tag] = self.take_array()?;
+		match tag {
+			tag::BOOLEAN_TRUE => Ok(true),
+			tag::BOOLEAN_FALSE => Ok(false),
+			other => Err(invalid(format!(
+				"expected a boolean but got tag {:?}",
+				char::from(other)
+			))),
+		}
+	}
+
+	pub fn string(&mut self) -> io::Result<Option<String>> {
+		let [tag] = self.take_array()?;
+		match tag {
+			tag::STRING_NULL => Ok(None),
+			tag::STRING => {
+				let len = self
+					.data
+					.iter()
+					.position(|&byte| byte == 0)
+					.ok_or_else(|| invalid("unterminated string"))?;
+				let value = String::from_utf8_lossy(self.take(len)?).into_owned();
+				self.take(1)?;
+				Ok(Some(value))
+			}
+			other => Err(invalid(format!(
+				"expected a string but got tag {:?}",
+				char::from(other)
+			))),
+		}
+	}
+
+	pub fn arbitrary(&mut self) -> io::Result<&'a [u8]> {
+		self.expect_tag(tag::ARBITRARY)?;
+		let len = self.raw_u32()?;
+		self.take(len as usize)
+	}
+
+	/// Skips a sample specification, the contents of which we never need.
+	pub fn sample_spec(&mut self) -> io::Result<()> {
+		self.expect_tag(tag::SAMPLE_SPEC)?;
+		let _format_and_channels: [u8; 2] = self.take_array()?;
+		let _rate = self.raw_u32()?;
+		Ok(())
+	}
+
+	pub fn channel_map(&mut self) -> io::Result<Vec<u8>> {
+		self.expect_tag(tag::CHANNEL_MAP)?;
+		let [channels] = self.take_array()?;
+		Ok(self.take(channels.into())?.to_vec())
+	}
+
+	pub fn cvolume(&mut self) -> io::Result<Vec<u32>> {
+		self.expect_tag(tag::CVOLUME)?;
+		let [channels] = self.take_array()?;
+		(0..channels).map(|_| self.raw_u32()).collect()
+	}
+
+	pub fn volume(&mut self) -> io::Result<u32> {
+		self.expect_tag(tag::VOLUME)?;
+		self.raw_u32()
+	}
+
+	/// Reads a property list.
+	///
+	/// Values are arbitrary bytes on the wire, but in practice they are always NUL-terminated strings.
+	pub fn properties(&mut self) -> io::Result<Properties> {
+		self.expect_tag(tag::PROPLIST)?;
+		let mut properties = Properties::new();
+		while let Some(key) = self.string()? {
+			let len = self.u32()?;
+			let value = self.arbitrary()?;
+			if value.len() != len as usize {
+				return Err(invalid("property length mismatch"));
+			}
+			let value = value.strip_suffix(&[0]).unwrap_or(value);
+			properties.insert(key, String::from_utf8_lossy(value).into_owned());
+		}
+		Ok(properties)
+	}
+
+	pub fn format_info(&mut self) -> io::Result<(u8, Properties)> {
+		self.expect_tag(tag::FORMAT_INFO)?;
+		let encoding = self.u8()?;
+		let properties = self.properties()?;
+		Ok((encoding, properties))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn properties() -> Properties {
+		Properties::from([
+			("application.name".into(), "mpv".into()),
+			("media.name".into(), String::new()),
+		])
+	}
+
+	/// One of each tag.
+	fn everything() -> Vec<u8> {
+		let mut writer = Writer::default();
+		writer
+			.u32(0xdead_beef)
+			.string(Some("hello"))
+			.string(None)
+			.string(Some(""))
+			.u8(7)
+			.bool(true)
+			.bool(false)
+			.sample_spec(5, 2, 48_000)
+			.channel_map(&[1, 2])
+			.cvolume(&[0x10000, 0])
+			.arbitrary(b"\x00\x01\x02")
+			.properties(&properties())
+			.usec(u64::MAX)
+			.volume(0x8000)
+			.format_info(1, &properties());
+		writer.into_bytes()
+	}
+
+	fn read_everything(reader: &mut Reader<'_>) -> io::Result<()> {
+		assert_eq!(reader.u32()?, 0xdead_beef);
+		assert_eq!(reader.string()?.as_deref(), Some("hello"));
+		assert_eq!(reader.string()?, None);
+		assert_eq!(reader.string()?.as_deref(), Some(""));
+		assert_eq!(reader.u8()?, 7);
+		assert!(reader.bool()?);
+		assert!(!reader.bool()?);
+		reader.sample_spec()?;
+		assert_eq!(reader.channel_map()?, [1, 2]);
+		assert_eq!(reader.cvolume()?, [0x10000, 0]);
+		assert_eq!(reader.arbitrary()?, b"\x00\x01\x02");
+		assert_eq!(reader.properties()?, properties());
+		assert_eq!(reader.usec()?, u64::MAX);
+		assert_eq!(reader.volume()?, 0x8000);
+		assert_eq!(reader.format_info()?, (1, properties()));
+		Ok(())
+	}
+
+	#[test]
+	fn round_trip() {
+		let data = everything();
+		let mut reader = Reader::new(&data);
+		read_everything(&mut reader).unwrap();
+		assert!(reader.is_empty());
+	}
+
+	#[test]
+	fn truncated() {
+		let data = everything();
+		for len in 0..data.len() {
+			assert!(
+				read_everything(&mut Reader::new(&data[..len])).is_err(),
+				"{len} bytes"
+			);
+		}
+	}
+
+	#[test]
+	fn malformed() {
+		let mut writer = Writer::default();
+		writer.u8(1);
+		assert!(Reader::new(&writer.into_bytes()).u32().is_err());
+
+		// A property whose length disagrees with its value.
+		let mut writer = Writer::default();
+		writer.string(Some("media.name")).u32(5).arbitrary(b"ab\0");
+		let mut data = vec![tag::PROPLIST];
+		data.extend(writer.into_bytes());
+		assert!(Reader::new(&data).properties().is_err());
+
+		assert!(Reader::new(b"tunterminated").string().is_err());
+	}
+}
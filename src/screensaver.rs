@@ -0,0 +1,116 @@
+//! Inhibiting idle through `org.freedesktop.ScreenSaver` on the session bus, for desktops and idle daemons that listen there rather than to the compositor.
+
+use zbus::blocking::Connection;
+use zbus::proxy;
+
+use crate::inhibit::{self, Target};
+
+#[proxy(
+	interface = "org.freedesktop.ScreenSaver",
+	default_service = "org.freedesktop.ScreenSaver",
+	default_path = "/org/freedesktop/ScreenSaver",
+	gen_async = false,
+	blocking_name = "ServiceProxy"
+)]
+trait Service {
+	fn inhibit(&self, application_name: &str, reason_for_inhibit: &str) -> zbus::Result<u32>;
+	fn un_inhibit(&self, cookie: u32) -> zbus::Result<()>;
+}
+
+pub struct ScreenSaver {
+	service: ServiceProxy<'static>,
+	/// The cookie to withdraw the inhibit with, while inhibited.
+	cookie: Option<u32>,
+}
+
+impl ScreenSaver {
+	/// Connects to the session bus. Whether anything provides the service is only known once idle is inhibited.
+	pub fn connect() -> zbus::Result<Self> {
+		Self::new(&Connection::session()?)
+	}
+
+	pub fn new(connection: &Connection) -> zbus::Result<Self> {
+		Ok(Self {
+			service: ServiceProxy::new(connection)?,
+			cookie: None,
+		})
+	}
+}
+
+impl Target for ScreenSaver {
+	fn set_inhibited(&mut self, inhibited: bool) {
+		match (inhibited, self.cookie) {
+			(true, None) => match self.service.inhibit(env!("CARGO_PKG_NAME"), inhibit::WHY) {
+				Ok(cookie) => self.cookie = Some(cookie),
+				Err(error) => eprintln!("cannot inhibit idle through org.freedesktop.ScreenSaver: {error}"),
+			},
+			(false, Some(cookie)) => {
+				self.cookie = None;
+				if let Err(error) = self.service.un_inhibit(cookie) {
+					eprintln!("cannot stop inhibiting idle through org.freedesktop.ScreenSaver: {error}");
+				}
+			}
+			_ => {}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+
+	use zbus::interface;
+
+	use super::*;
+	use crate::test_bus::{self, TestBus};
+
+	/// A cookie in use, and the arguments it was asked for with.
+	type Cookie = (u32, [String; 2]);
+
+	/// Numbers cookies from 1, in the order they are asked for.
+	#[derive(Default)]
+	struct Stub {
+		cookies: Arc<Mutex<Vec<Cookie>>>,
+		next: u32,
+	}
+
+	#[interface(name = "org.freedesktop.ScreenSaver")]
+	impl Stub {
+		fn inhibit(&mut self, application_name: &str, reason_for_inhibit: &str) -> u32 {
+			self.next += 1;
+			let arguments = [application_name, reason_for_inhibit].map(str::to_owned);
+			self.cookies.lock().unwrap().push((self.next, arguments));
+			self.next
+		}
+
+		fn un_inhibit(&mut self, cookie: u32) {
+			self
+				.cookies
+				.lock()
+				.unwrap()
+				.retain(|&(other, _)| other != cookie);
+		}
+	}
+
+	#[test]
+	fn inhibits() {
+		let Some(bus) = TestBus::start() else {
+			return;
+		};
+		let stub = Stub::default();
+		let cookies = Arc::clone(&stub.cookies);
+		let _service = bus.serve(
+			"org.freedesktop.ScreenSaver",
+			"/org/freedesktop/ScreenSaver",
+			stub,
+		);
+
+		let mut screen_saver = ScreenSaver::new(&bus.connect()).unwrap();
+		test_bus::check_round_trips(&mut screen_saver, || cookies.lock().unwrap().len());
+
+		// Each inhibit gets a new cookie, which has to be the one passed back to stop it.
+		screen_saver.set_inhibited(true);
+		let arguments = [env!("CARGO_PKG_NAME"), inhibit::WHY].map(str::to_owned);
+		assert_eq!(*cookies.lock().unwrap(), [(3, arguments)]);
+	}
+}
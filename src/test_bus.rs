@@ -0,0 +1,76 @@
+//! A D-Bus daemon of our own, to test targets against stub services on.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+use zbus::blocking::Connection;
+use zbus::object_server::Interface;
+
+use crate::inhibit::Target;
+
+/// Stopped when dropped.
+pub struct TestBus {
+	daemon: Child,
+	address: String,
+}
+
+impl TestBus {
+	/// Starts the daemon, or returns `None` if `dbus-daemon` is not installed.
+	pub fn start() -> Option<Self> {
+		let Ok(mut daemon) = Command::new("dbus-daemon")
+			.args(["--session", "--nofork", "--print-address=1"])
+			.stdout(Stdio::piped())
+			.stderr(Stdio::null())
+			.spawn()
+		else {
+			eprintln!("skipping, as dbus-daemon is not installed");
+			return None;
+		};
+		let mut address = String::new();
+		BufReader::new(daemon.stdout.take().unwrap())
+			.read_line(&mut address)
+			.unwrap();
+		address.truncate(address.trim_end().len());
+		Some(Self { daemon, address })
+	}
+
+	/// A new connection to the bus.
+	pub fn connect(&self) -> Connection {
+		self.builder().build().unwrap()
+	}
+
+	/// Serves `stub` at `path` under the well-known `name`, for as long as the returned connection is kept.
+	pub fn serve(&self, name: &str, path: &str, stub: impl Interface) -> Connection {
+		self
+			.builder()
+			.name(name)
+			.unwrap()
+			.serve_at(path, stub)
+			.unwrap()
+			.build()
+			.unwrap()
+	}
+
+	fn builder(&self) -> zbus::blocking::connection::Builder<'_> {
+		zbus::blocking::connection::Builder::address(self.address.as_str()).unwrap()
+	}
+}
+
+impl Drop for TestBus {
+	fn drop(&mut self) {
+		_ = self.daemon.kill();
+		_ = self.daemon.wait();
+	}
+}
+
+/// Checks that `target` inhibits idle once, however often it is told to, and stops when told to, going by `held`: how many inhibits the stub service has in place.
+pub fn check_round_trips(target: &mut dyn Target, held: impl Fn() -> usize) {
+	for _ in 0..2 {
+		target.set_inhibited(true);
+		target.set_inhibited(true);
+		assert_eq!(held(), 1);
+		target.set_inhibited(false);
+		target.set_inhibited(false);
+		assert_eq!(held(), 0);
+	}
+}
@@ -0,0 +1,229 @@
+//! When to inhibit, given when audio is active: the activation delay and linger. Also manual inhibits, which last until they expire.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timings {
+	/// How long to wait for a burst of audio events to settle before re-checking.
+	pub debounce: Duration,
+	/// How long audio has to be active before we inhibit, so that short sounds don't count.
+	pub activation_delay: Duration,
+	/// How long to keep inhibiting after audio stops, so that briefly pausing doesn't let the screen blank.
+	pub linger: Duration,
+}
+
+impl Default for Timings {
+	fn default() -> Self {
+		Self {
+			debounce: Duration::from_secs(1),
+			activation_delay: Duration::ZERO,
+			linger: Duration::ZERO,
+		}
+	}
+}
+
+/// Decides whether to inhibit from whether audio is active over time.
+#[derive(Debug)]
+pub struct Decision {
+	timings: Timings,
+	inhibited: bool,
+	/// When audio started being active while we are not inhibiting, or inactive while we are.
+	pending_since: Option<Instant>,
+}
+
+impl Decision {
+	pub fn new(timings: Timings) -> Self {
+		Self {
+			timings,
+			inhibited: false,
+			pending_since: None,
+		}
+	}
+
+	/// Changes the timings, keeping whether we inhibit and since when audio has been active or inactive.
+	pub fn set_timings(&mut self, timings: Timings) {
+		self.timings = timings;
+	}
+
+	/// Takes note of whether audio is active at `now`.
+	///
+	/// Returns whether to inhibit, and if that will change without audio activity changing, when to call this again.
+	pub fn update(&mut self, active: bool, now: Instant) -> (bool, Option<Instant>) {
+		if active == self.inhibited {
+			self.pending_since = None;
+			return (self.inhibited, None);
+		}
+
+		let since = *self.pending_since.get_or_insert(now);
+		let delay = if active {
+			self.timings.activation_delay
+		} else {
+			self.timings.linger
+		};
+		let deadline = since + delay;
+		if now >= deadline {
+			self.inhibited = active;
+			self.pending_since = None;
+			(self.inhibited, None)
+		} else {
+			(self.inhibited, Some(deadline))
+		}
+	}
+}
+
+/// A manual inhibit, which lasts until it expires or is withdrawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Manual {
+	#[default]
+	Off,
+	Until(Instant),
+	Indefinitely,
+}
+
+impl Manual {
+	/// A manual inhibit starting at `now` that lasts for `duration`, or indefinitely.
+	pub fn new(duration: Option<Duration>, now: Instant) -> Self {
+		duration.map_or(Self::Indefinitely, |duration| Self::Until(now + duration))
+	}
+
+	/// Whether it is in force at `now`, turning it off if it has expired.
+	pub fn update(&mut self, now: Instant) -> bool {
+		match *self {
+			Self::Off => false,
+			Self::Until(until) if until <= now => {
+				*self = Self::Off;
+				false
+			}
+			Self::Until(_) | Self::Indefinitely => true,
+		}
+	}
+
+	/// When it expires, if it does.
+	pub fn deadline(self) -> Option<Instant> {
+		match self {
+			Self::Until(until) => Some(until),
+			Self::Off | Self::Indefinitely => None,
+		}
+	}
+}
+
+/// Parses a duration such as `90`, `45m`, `1h30m` or `1.5h`. A number without a unit is in seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+	let invalid = || format!("{value:?} is not a valid duration, such as 90, 45m or 1h30m");
+	let mut total = 0.0;
+	let mut rest = value.trim();
+	if rest.is_empty() {
+		return Err(invalid());
+	}
+	while !rest.is_empty() {
+		let end = rest
+			.find(|c: char| !c.is_ascii_digit() && c != '.')
+			.unwrap_or(rest.len());
+		let (number, after) = rest.split_at(end);
+		let number: f64 = number.parse().map_err(|_| invalid())?;
+		let unit_end = after
+			.find(|c: char| c.is_ascii_digit() || c == '.')
+			.unwrap_or(after.len());
+		let (unit, after) = after.split_at(unit_end);
+		let scale = match unit {
+			"" | "s" => 1.0,
+			"m" => 60.0,
+			"h" => 3600.0,
+			"d" => 86400.0,
+			_ => return Err(invalid()),
+		};
+		total += number * scale;
+		rest = after;
+	}
+	Duration::try_from_secs_f64(total).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn immediate() {
+		let mut decision = Decision::new(Timings::default());
+		let now = Instant::now();
+		assert_eq!(decision.update(true, now), (true, None));
+		assert_eq!(decision.update(false, now), (false, None));
+	}
+
+	#[test]
+	fn activation_delay() {
+		let mut decision = Decision::new(Timings {
+			activation_delay: Duration::from_secs(5),
+			..Timings::default()
+		});
+		let start = Instant::now();
+		let deadline = start + Duration::from_secs(5);
+		assert_eq!(decision.update(true, start), (false, Some(deadline)));
+		assert_eq!(
+			decision.update(true, start + Duration::from_secs(2)),
+			(false, Some(deadline))
+		);
+		assert_eq!(decision.update(true, deadline), (true, None));
+
+		// A short sound in between doesn't count towards the next one.
+		assert_eq!(decision.update(false, deadline), (false, None));
+		let later = deadline + Duration::from_secs(10);
+		assert_eq!(
+			decision.update(true, later),
+			(false, Some(later + Duration::from_secs(5)))
+		);
+		assert_eq!(
+			decision.update(false, later + Duration::from_secs(2)),
+			(false, None)
+		);
+	}
+
+	#[test]
+	fn linger() {
+		let mut decision = Decision::new(Timings {
+			linger: Duration::from_mins(1),
+			..Timings::default()
+		});
+		let start = Instant::now();
+		assert_eq!(decision.update(true, start), (true, None));
+		let paused = start + Duration::from_secs(100);
+		let deadline = paused + Duration::from_mins(1);
+		assert_eq!(decision.update(false, paused), (true, Some(deadline)));
+		// Resuming before the deadline cancels it.
+		assert_eq!(
+			decision.update(true, paused + Duration::from_secs(30)),
+			(true, None)
+		);
+		let paused = paused + Duration::from_secs(40);
+		let deadline = paused + Duration::from_mins(1);
+		assert_eq!(decision.update(false, paused), (true, Some(deadline)));
+		assert_eq!(decision.update(false, deadline), (false, None));
+	}
+
+	#[test]
+	fn manual() {
+		let now = Instant::now();
+		let mut manual = Manual::new(Some(Duration::from_mins(45)), now);
+		assert!(manual.update(now + Duration::from_mins(44)));
+		assert_eq!(manual.deadline(), Some(now + Duration::from_mins(45)));
+		assert!(!manual.update(now + Duration::from_mins(45)));
+		assert_eq!(manual, Manual::Off);
+
+		let mut manual = Manual::new(None, now);
+		assert!(manual.update(now + Duration::from_hours(100)));
+		assert_eq!(manual.deadline(), None);
+	}
+
+	#[test]
+	fn durations() {
+		assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+		assert_eq!(parse_duration("45m"), Ok(Duration::from_mins(45)));
+		assert_eq!(parse_duration("1h30m"), Ok(Duration::from_mins(90)));
+		assert_eq!(parse_duration("1.5h"), Ok(Duration::from_mins(90)));
+		assert_eq!(parse_duration("2m30"), Ok(Duration::from_secs(150)));
+		assert!(parse_duration("").is_err());
+		assert!(parse_duration("soon").is_err());
+		assert!(parse_duration("5 minutes").is_err());
+		assert!(parse_duration("1.2.3m").is_err());
+	}
+}